@@ -1,19 +1,499 @@
-use crate::connection::{Connection, ConnectionError, ConnectionOptions};
+use crate::command::{self, Command, CommandError, FromSegmentFrame};
+use crate::connection::{ConfigError, Connection, ConnectionError, ConnectionOptions, Dialect};
+use crate::resolver::Resolver;
+use crate::runtime::Runtime;
+use std::net::SocketAddr;
+#[cfg(feature = "hdrhistogram")]
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(feature = "hdrhistogram")]
+use std::time::Instant;
 
 #[derive(Debug)]
 /// Segment client
 pub struct Client {
     options: ConnectionOptions,
+    retries: usize,
+    #[cfg(feature = "hdrhistogram")]
+    latencies: Mutex<hdrhistogram::Histogram<u64>>,
+}
+
+/// A copy of the latencies recorded by a client, in microseconds from 1µs up to a minute
+#[cfg(feature = "hdrhistogram")]
+#[derive(Debug, Clone)]
+pub struct LatencySnapshot {
+    histogram: hdrhistogram::Histogram<u64>,
+}
+
+#[derive(Debug)]
+/// Builds a configured Segment client
+pub struct ClientBuilder {
+    options: ConnectionOptions,
+    retries: usize,
 }
 
 impl Client {
     /// Creates a new client, this does not create a new connection
     pub fn new(options: ConnectionOptions) -> Self {
-        Client { options }
+        Client {
+            options,
+            retries: 0,
+            #[cfg(feature = "hdrhistogram")]
+            latencies: Mutex::new(latency_histogram()),
+        }
+    }
+
+    /// Creates a new client builder, this is the recommended way of creating a configured client
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder {
+            options: ConnectionOptions::default(),
+            retries: 0,
+        }
+    }
+
+    /// Returns the options used to create connections
+    pub fn options(&self) -> &ConnectionOptions {
+        &self.options
+    }
+
+    /// Returns the number of times idempotent commands are retried by `query`
+    pub fn retries(&self) -> usize {
+        self.retries
+    }
+
+    /// Returns a copy of the latencies of the queries executed through the client, including their retries.
+    /// Every query made with `query` or `query_with_retry` is recorded once, while commands executed directly on a
+    /// connection, e.g. with `Connection::command`, bypass the client and are not recorded
+    #[cfg(feature = "hdrhistogram")]
+    pub fn latency_snapshot(&self) -> LatencySnapshot {
+        LatencySnapshot {
+            histogram: self
+                .latencies
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .clone(),
+        }
     }
 
     /// Creates a new connection
     pub async fn get_connection(&self) -> Result<Connection, ConnectionError> {
         Connection::connect(&self.options).await
     }
+
+    /// Executes the command on the connection, retrying idempotent commands as many times as configured on the client
+    pub async fn query<T: FromSegmentFrame>(
+        &self,
+        connection: &mut Connection,
+        cmd: Command,
+    ) -> Result<T, CommandError> {
+        self.query_with_retry(connection, cmd, self.retries).await
+    }
+
+    /// Executes the command on the connection. If the command is idempotent and fails with a transient connection error,
+    /// e.g. an I/O error or a timeout, the connection is re-established and the command is retried up to `retries` times.
+    /// Failing to re-establish the connection counts as one of the retries
+    pub async fn query_with_retry<T: FromSegmentFrame>(
+        &self,
+        connection: &mut Connection,
+        cmd: Command,
+        retries: usize,
+    ) -> Result<T, CommandError> {
+        let idempotent = cmd.is_idempotent();
+        let frame = cmd.into_frame();
+        let mut attempts = 0;
+        let mut reconnect = false;
+        #[cfg(feature = "hdrhistogram")]
+        let started = Instant::now();
+
+        loop {
+            let result = if reconnect {
+                match self.get_connection().await {
+                    Ok(new) => {
+                        *connection = new;
+                        command::execute(&frame, connection).await
+                    }
+                    Err(e) => Err(e.into()),
+                }
+            } else {
+                command::execute(&frame, connection).await
+            };
+            match result {
+                Err(CommandError::ConnectionError(ref e))
+                    if idempotent && attempts < retries && e.is_transient() =>
+                {
+                    attempts += 1;
+                    reconnect = true;
+                }
+                result => {
+                    #[cfg(feature = "hdrhistogram")]
+                    self.latencies
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .saturating_record(started.elapsed().as_micros() as u64);
+                    return result;
+                }
+            }
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Sets the host to connect to
+    pub fn host(&mut self, host: &str) -> &mut Self {
+        self.options.set_host(host);
+        self
+    }
+
+    /// Sets the port to connect to
+    pub fn port(&mut self, port: u16) -> &mut Self {
+        self.options.set_port(port);
+        self
+    }
+
+    /// Sets the maximum number of bytes the read buffer grows by once it is full
+    pub fn max_buffer_growth(&mut self, growth: usize) -> &mut Self {
+        self.options.set_max_buffer_growth(growth);
+        self
+    }
+
+    /// Sets the maximum number of bytes a single frame read from the server can take
+    pub fn max_frame_size(&mut self, max_frame_size: usize) -> &mut Self {
+        self.options.set_max_frame_size(max_frame_size);
+        self
+    }
+
+    /// Sets the maximum number of elements a single frame read from the server can contain
+    pub fn max_elements(&mut self, max_elements: usize) -> &mut Self {
+        self.options.set_max_elements(max_elements);
+        self
+    }
+
+    /// Sets the timeout for reading a frame
+    pub fn read_timeout(&mut self, read_timeout: Duration) -> &mut Self {
+        self.options.set_read_timeout(read_timeout);
+        self
+    }
+
+    /// Sets the timeout for writing a frame
+    pub fn write_timeout(&mut self, write_timeout: Duration) -> &mut Self {
+        self.options.set_write_timeout(write_timeout);
+        self
+    }
+
+    /// Sets how long a query can take before it is logged as slow
+    pub fn slow_query_threshold(&mut self, slow_query_threshold: Duration) -> &mut Self {
+        self.options.set_slow_query_threshold(slow_query_threshold);
+        self
+    }
+
+    /// Sets the runtime connections are driven by
+    pub fn runtime(&mut self, runtime: Runtime) -> &mut Self {
+        self.options.set_runtime(runtime);
+        self
+    }
+
+    /// Sets the dialect used to encode commands
+    pub fn dialect(&mut self, dialect: Dialect) -> &mut Self {
+        self.options.set_dialect(dialect);
+        self
+    }
+
+    /// Sets the local address connections are bound to before connecting
+    pub fn bind_addr(&mut self, bind_addr: SocketAddr) -> &mut Self {
+        self.options.set_bind_addr(bind_addr);
+        self
+    }
+
+    /// Sets the resolver used to look up the host
+    pub fn resolver<R: Resolver + 'static>(&mut self, resolver: R) -> &mut Self {
+        self.options.set_resolver(resolver);
+        self
+    }
+
+    /// Sets the number of times idempotent commands are retried after a connection error
+    pub fn retries(&mut self, retries: usize) -> &mut Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Validates the options and builds the client
+    pub fn build(&self) -> Result<Client, ConfigError> {
+        self.options.validate()?;
+        Ok(Client {
+            options: self.options.clone(),
+            retries: self.retries,
+            #[cfg(feature = "hdrhistogram")]
+            latencies: Mutex::new(latency_histogram()),
+        })
+    }
+}
+
+#[cfg(feature = "hdrhistogram")]
+impl LatencySnapshot {
+    /// Returns the number of recorded queries
+    pub fn count(&self) -> u64 {
+        self.histogram.len()
+    }
+
+    /// Returns the latency at the percentile, e.g. 99.0 for the p99. Returns zero if nothing was recorded
+    pub fn percentile(&self, percentile: f64) -> Duration {
+        Duration::from_micros(self.histogram.value_at_percentile(percentile))
+    }
+
+    /// Returns the lowest recorded latency
+    pub fn min(&self) -> Duration {
+        Duration::from_micros(self.histogram.min())
+    }
+
+    /// Returns the highest recorded latency
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.histogram.max())
+    }
+
+    /// Returns the mean of the recorded latencies
+    pub fn mean(&self) -> Duration {
+        Duration::from_secs_f64(self.histogram.mean() / 1_000_000.0)
+    }
+}
+
+/// Creates the histogram latencies are recorded in, with 3 significant digits between 1µs and a minute
+#[cfg(feature = "hdrhistogram")]
+fn latency_histogram() -> hdrhistogram::Histogram<u64> {
+    hdrhistogram::Histogram::new_with_bounds(1, 60_000_000, 3).expect("valid histogram bounds")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::PipelineGuard;
+    use crate::frame::Frame;
+    use crate::resolver::ResolveFuture;
+    use crate::test_server::TestServer;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+
+    /// Starts a server which drops the first connection after reading a command and replies with 1 on the others
+    async fn flaky_server() -> (ConnectionOptions, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = ConnectionOptions::new("127.0.0.1", listener.local_addr().unwrap().port());
+        let accepted = Arc::new(AtomicUsize::new(0));
+        let counter = accepted.clone();
+        let server_options = ConnectionOptions::new("127.0.0.1", options.port());
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let first = counter.fetch_add(1, Ordering::SeqCst) == 0;
+                let mut connection = Connection::from_stream(stream, &server_options);
+                if connection.read_frame().await.is_ok() && !first {
+                    connection.write_frame(&Frame::Integer(1)).await.unwrap();
+                }
+            }
+        });
+        (options, accepted)
+    }
+
+    #[cfg(feature = "hdrhistogram")]
+    #[tokio::test]
+    async fn latency_snapshot_reflects_recorded_queries() {
+        let server =
+            TestServer::start_delayed(Duration::from_millis(2), |_| Frame::Integer(1)).await;
+        let client = Client::new(server.options());
+        let mut connection = client.get_connection().await.unwrap();
+        assert_eq!(client.latency_snapshot().count(), 0);
+
+        for _ in 0..3 {
+            let _: i64 = client
+                .query(&mut connection, Command::from_args(&["GET", "foo"]))
+                .await
+                .unwrap();
+        }
+        let snapshot = client.latency_snapshot();
+        assert_eq!(snapshot.count(), 3);
+        assert!(snapshot.min() >= Duration::from_millis(2));
+        assert!(snapshot.percentile(50.0) >= Duration::from_millis(2));
+        assert!(snapshot.max() >= snapshot.percentile(50.0))
+    }
+
+    #[cfg(feature = "hdrhistogram")]
+    #[tokio::test]
+    async fn latency_snapshot_given_command_on_connection_does_not_record_it() {
+        let server = TestServer::start(|_| Frame::Integer(1)).await;
+        let client = Client::new(server.options());
+        let mut connection = client.get_connection().await.unwrap();
+
+        let _: i64 = connection.command(&["GET", "foo"]).await.unwrap();
+        assert_eq!(client.latency_snapshot().count(), 0)
+    }
+
+    #[tokio::test]
+    async fn query_with_retry_given_non_idempotent_command_does_not_retry() {
+        let (options, accepted) = flaky_server().await;
+        let client = Client::new(options);
+        let mut connection = client.get_connection().await.unwrap();
+
+        let mut cmd = Command::new();
+        cmd.arg("INCR").arg("foo");
+        let result = client
+            .query_with_retry::<i64>(&mut connection, cmd, 3)
+            .await;
+        assert!(matches!(result, Err(CommandError::ConnectionError(_))));
+        assert_eq!(accepted.load(Ordering::SeqCst), 1)
+    }
+
+    #[tokio::test]
+    async fn query_with_retry_given_non_transient_error_does_not_retry() {
+        let (options, _accepted) = flaky_server().await;
+        let client = Client::new(options);
+        let mut connection = client.get_connection().await.unwrap();
+        connection.set_pipeline_guard(PipelineGuard::Strict);
+        connection.write_frame(&Frame::Integer(1)).await.unwrap();
+
+        let mut cmd = Command::new();
+        cmd.arg("GET").arg("foo");
+        let result = client
+            .query_with_retry::<i64>(&mut connection, cmd.idempotent(), 3)
+            .await;
+        assert!(matches!(
+            result,
+            Err(CommandError::ConnectionError(
+                ConnectionError::UnreadResponses(1)
+            ))
+        ));
+        assert_eq!(connection.pending_responses(), 1)
+    }
+
+    /// Resolves to a closed port on the second resolution and to the address on every other one
+    #[derive(Debug)]
+    struct RefusedOnceResolver {
+        addr: SocketAddr,
+        refused: SocketAddr,
+        calls: AtomicUsize,
+    }
+
+    impl Resolver for RefusedOnceResolver {
+        fn resolve<'a>(&'a self, _host: &'a str, _port: u16) -> ResolveFuture<'a> {
+            let addr = match self.calls.fetch_add(1, Ordering::SeqCst) {
+                1 => self.refused,
+                _ => self.addr,
+            };
+            Box::pin(async move { Ok(vec![addr]) })
+        }
+    }
+
+    /// Fails the second resolution and resolves to the address on every other one
+    #[derive(Debug)]
+    struct FailsOnceResolver {
+        addr: SocketAddr,
+        calls: AtomicUsize,
+    }
+
+    impl Resolver for FailsOnceResolver {
+        fn resolve<'a>(&'a self, _host: &'a str, _port: u16) -> ResolveFuture<'a> {
+            let result = match self.calls.fetch_add(1, Ordering::SeqCst) {
+                1 => Err(io::Error::other("temporary failure")),
+                _ => Ok(vec![self.addr]),
+            };
+            Box::pin(async move { result })
+        }
+    }
+
+    #[tokio::test]
+    async fn query_with_retry_given_dns_failure_on_reconnect_retries() {
+        let (mut options, accepted) = flaky_server().await;
+        options.set_resolver(FailsOnceResolver {
+            addr: SocketAddr::from(([127, 0, 0, 1], options.port())),
+            calls: AtomicUsize::new(0),
+        });
+        let client = Client::new(options);
+        let mut connection = client.get_connection().await.unwrap();
+
+        let mut cmd = Command::new();
+        cmd.arg("GET").arg("foo");
+        let result = client
+            .query_with_retry::<i64>(&mut connection, cmd.idempotent(), 3)
+            .await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(accepted.load(Ordering::SeqCst), 2)
+    }
+
+    #[tokio::test]
+    async fn query_with_retry_given_failed_reconnect_counts_it_as_attempt() {
+        let (mut options, accepted) = flaky_server().await;
+        let closed = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refused = closed.local_addr().unwrap();
+        drop(closed);
+        options.set_resolver(RefusedOnceResolver {
+            addr: SocketAddr::from(([127, 0, 0, 1], options.port())),
+            refused,
+            calls: AtomicUsize::new(0),
+        });
+        let client = Client::new(options);
+        let mut connection = client.get_connection().await.unwrap();
+
+        let mut cmd = Command::new();
+        cmd.arg("GET").arg("foo");
+        let result = client
+            .query_with_retry::<i64>(&mut connection, cmd.idempotent(), 3)
+            .await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(accepted.load(Ordering::SeqCst), 2)
+    }
+
+    #[tokio::test]
+    async fn query_with_retry_given_idempotent_command_retries_on_new_connection() {
+        let (options, accepted) = flaky_server().await;
+        let client = Client::new(options);
+        let mut connection = client.get_connection().await.unwrap();
+
+        let mut cmd = Command::new();
+        cmd.arg("GET").arg("foo");
+        let result = client
+            .query_with_retry::<i64>(&mut connection, cmd.idempotent(), 3)
+            .await;
+        assert_eq!(result.unwrap(), 1);
+        assert_eq!(accepted.load(Ordering::SeqCst), 2)
+    }
+
+    #[test]
+    fn builder_given_settings_applies_them() {
+        let client = Client::builder()
+            .host("segment.local")
+            .port(1700)
+            .max_buffer_growth(4096)
+            .dialect(Dialect::BulkStrings)
+            .retries(3)
+            .build()
+            .unwrap();
+
+        assert_eq!(client.options().host(), "segment.local");
+        assert_eq!(client.options().port(), 1700);
+        assert_eq!(client.options().max_buffer_growth(), Some(4096));
+        assert_eq!(client.options().dialect(), Dialect::BulkStrings);
+        assert_eq!(client.retries(), 3)
+    }
+
+    #[test]
+    fn builder_given_invalid_settings_returns_config_error() {
+        assert_eq!(
+            Client::builder().port(0).build().unwrap_err(),
+            ConfigError::ZeroPort
+        )
+    }
+
+    #[tokio::test]
+    async fn get_connection_given_test_server_round_trips_command() {
+        let server = TestServer::start(|frame| match frame {
+            Frame::Array(mut args) => args.pop().unwrap_or(Frame::Null),
+            _ => Frame::Error("invalid command".into()),
+        })
+        .await;
+        let client = Client::new(server.options());
+        let mut connection = client.get_connection().await.unwrap();
+
+        let mut cmd = Command::new();
+        cmd.arg("ECHO").arg("foo");
+        let reply: String = cmd.query(&mut connection).await.unwrap();
+        assert_eq!(reply, "foo")
+    }
 }
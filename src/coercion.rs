@@ -0,0 +1,130 @@
+use std::cell::Cell;
+
+/// Controls which conversions between frame kinds are allowed when decoding a response.
+/// The default is strict and only allows decoding a frame into its own kind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Coercions {
+    int_to_float: bool,
+    float_to_int: bool,
+    int_to_bool: bool,
+    bool_to_int: bool,
+    string_to_number: bool,
+    number_to_string: bool,
+    string_to_bool: bool,
+}
+
+thread_local! {
+    static COERCIONS: Cell<Option<Coercions>> = const { Cell::new(None) };
+}
+
+impl Coercions {
+    /// Creates strict coercions which do not allow any conversion between frame kinds
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the coercions used when none are given, which allow converting between integers and doubles
+    pub fn numeric() -> Self {
+        let mut coercions = Self::new();
+        coercions.set_int_to_float(true).set_float_to_int(true);
+        coercions
+    }
+
+    /// Sets whether integers can be decoded into floating point types
+    pub fn set_int_to_float(&mut self, allow: bool) -> &mut Self {
+        self.int_to_float = allow;
+        self
+    }
+
+    /// Sets whether doubles holding whole numbers can be decoded into integer types.
+    /// Fractional doubles, NaN and doubles which do not fit the type still fail to decode
+    pub fn set_float_to_int(&mut self, allow: bool) -> &mut Self {
+        self.float_to_int = allow;
+        self
+    }
+
+    /// Sets whether the integers 0 and 1 can be decoded into booleans
+    pub fn set_int_to_bool(&mut self, allow: bool) -> &mut Self {
+        self.int_to_bool = allow;
+        self
+    }
+
+    /// Sets whether booleans can be decoded into integer types
+    pub fn set_bool_to_int(&mut self, allow: bool) -> &mut Self {
+        self.bool_to_int = allow;
+        self
+    }
+
+    /// Sets whether strings can be parsed into numeric types
+    pub fn set_string_to_number(&mut self, allow: bool) -> &mut Self {
+        self.string_to_number = allow;
+        self
+    }
+
+    /// Sets whether integers and doubles can be decoded into strings
+    pub fn set_number_to_string(&mut self, allow: bool) -> &mut Self {
+        self.number_to_string = allow;
+        self
+    }
+
+    /// Sets whether the strings "true" and "false" can be decoded into booleans, ignoring case
+    pub fn set_string_to_bool(&mut self, allow: bool) -> &mut Self {
+        self.string_to_bool = allow;
+        self
+    }
+
+    /// Returns true if integers can be decoded into floating point types
+    pub fn int_to_float(&self) -> bool {
+        self.int_to_float
+    }
+
+    /// Returns true if doubles can be decoded into integer types
+    pub fn float_to_int(&self) -> bool {
+        self.float_to_int
+    }
+
+    /// Returns true if the integers 0 and 1 can be decoded into booleans
+    pub fn int_to_bool(&self) -> bool {
+        self.int_to_bool
+    }
+
+    /// Returns true if booleans can be decoded into integer types
+    pub fn bool_to_int(&self) -> bool {
+        self.bool_to_int
+    }
+
+    /// Returns true if strings can be parsed into numeric types
+    pub fn string_to_number(&self) -> bool {
+        self.string_to_number
+    }
+
+    /// Returns true if integers and doubles can be decoded into strings
+    pub fn number_to_string(&self) -> bool {
+        self.number_to_string
+    }
+
+    /// Returns true if the strings "true" and "false" can be decoded into booleans
+    pub fn string_to_bool(&self) -> bool {
+        self.string_to_bool
+    }
+}
+
+/// Returns the coercions in effect for the decode running on the current thread
+pub(crate) fn current() -> Coercions {
+    COERCIONS.with(|coercions| coercions.get().unwrap_or_else(Coercions::numeric))
+}
+
+/// Runs the decode with the given coercions in effect on the current thread.
+/// The decode is synchronous, so the coercions can not leak into other tasks running on the same thread
+pub(crate) fn with_coercions<T>(coercions: Coercions, decode: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Coercions>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            COERCIONS.with(|coercions| coercions.set(self.0));
+        }
+    }
+
+    let _restore = Restore(COERCIONS.with(|current| current.replace(Some(coercions))));
+    decode()
+}
@@ -1,10 +1,16 @@
-use crate::connection::{Connection, ConnectionError};
-use crate::frame::Frame;
-use bytes::Bytes;
+use crate::coercion::{self, Coercions};
+use crate::connection::{Connection, ConnectionError, Dialect};
+use crate::frame::{self, Frame, FrameVisitor, ParseFrameError};
+use bytes::{Bytes, BytesMut};
 use std::any::type_name;
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::Hash;
+use std::ops::{Range, RangeInclusive};
+use std::rc::Rc;
 use std::str::{self, Utf8Error};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
 
 /// Used to convert a value to a Segment frame
@@ -23,8 +29,31 @@ pub trait FromSegmentFrame: Sized {
 #[derive(Debug)]
 pub struct Command {
     args: Vec<Frame>,
+    idempotent: bool,
 }
 
+/// A command serialized once so it can be sent many times without being encoded again
+#[derive(Debug, Clone)]
+pub struct PreparedCommand {
+    bytes: Bytes,
+    idempotent: bool,
+}
+
+/// A batch of commands which are written together and whose responses are read back in order
+#[derive(Debug, Default)]
+pub struct Pipeline {
+    commands: Vec<Command>,
+    max_commands: Option<usize>,
+}
+
+/// Wraps an integer which is encoded as a fixed width big endian byte string instead of an integer frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BigEndian<T>(pub T);
+
+/// Wraps an integer which is encoded as a fixed width little endian byte string instead of an integer frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LittleEndian<T>(pub T);
+
 /// Represents a command error
 #[derive(Debug, Error)]
 pub enum CommandError {
@@ -40,19 +69,99 @@ pub enum CommandError {
     #[error("{0}")]
     QueryError(String),
 
-    /// Represents a connection error
+    /// Represents a connection error. Frame parsing errors are reported by their own variants instead
     #[error(transparent)]
-    ConnectionError(#[from] ConnectionError),
+    ConnectionError(ConnectionError),
 
     /// Represents a frame decoding error
     #[error("failed to decode the frame")]
     Decode,
+
+    /// Occurs when a null is returned by the server for a type which is not an Option, e.g. when a key is missing
+    #[error("unexpected null: failed to convert to {0}")]
+    UnexpectedNull(&'static str),
+
+    /// Occurs when an integer does not fit in the requested type
+    #[error("integer out of range for {0}")]
+    OutOfRange(&'static str),
+
+    /// Occurs when a field is missing from a map decoded into a struct
+    #[error("missing field: {0}")]
+    MissingField(String),
+
+    /// Occurs when adding a command to a pipeline which already holds its maximum number of commands
+    #[error("pipeline is full: it holds at most {0} commands")]
+    PipelineFull(usize),
+
+    /// Occurs when the response ends in the middle of a frame
+    #[error("incomplete frame")]
+    IncompleteFrame,
+
+    /// Occurs when the response is not a valid frame. Holds what was being parsed and the byte offset of the frame
+    #[error("invalid {context} at offset {offset}")]
+    InvalidFrame {
+        /// The kind of value that failed to parse
+        context: &'static str,
+        /// Byte offset of the malformed frame from the start of the buffer
+        offset: usize,
+    },
+
+    /// Occurs when the response exceeds the maximum frame size or the maximum number of elements
+    #[error("frame exceeds the configured limits")]
+    FrameTooLarge,
+}
+
+impl From<ParseFrameError> for CommandError {
+    fn from(e: ParseFrameError) -> Self {
+        match e {
+            ParseFrameError::Incomplete => CommandError::IncompleteFrame,
+            ParseFrameError::InvalidFormat { context, offset } => {
+                CommandError::InvalidFrame { context, offset }
+            }
+            ParseFrameError::FrameTooLarge => CommandError::FrameTooLarge,
+        }
+    }
+}
+
+impl From<ConnectionError> for CommandError {
+    fn from(e: ConnectionError) -> Self {
+        match e {
+            ConnectionError::FrameError(e) => e.into(),
+            e => CommandError::ConnectionError(e),
+        }
+    }
 }
 
 impl Command {
     /// Creates an empty command
     pub fn new() -> Self {
-        Command { args: Vec::new() }
+        Command {
+            args: Vec::new(),
+            idempotent: false,
+        }
+    }
+
+    /// Creates a command from a slice of args
+    pub fn from_args<T: ToSegmentFrame>(args: &[T]) -> Self {
+        Command {
+            args: args.iter().map(ToSegmentFrame::to_segment_frame).collect(),
+            idempotent: false,
+        }
+    }
+
+    /// Creates a SET command with an expiry. Whole second ttls are sent as `EX seconds`,
+    /// anything with sub-second precision is sent as `PX milliseconds`, rounded up so a ttl never expires early
+    /// and saturating at `u64::MAX`. A zero ttl is sent as `EX 0`, which the server rejects as an invalid expire time
+    pub fn set_ex<V: ToSegmentFrame>(key: &str, value: V, ttl: Duration) -> Self {
+        let mut cmd = Command::new();
+        cmd.arg("SET").arg(key).arg(value);
+        if ttl.subsec_nanos() == 0 {
+            cmd.arg("EX").arg(ttl.as_secs());
+        } else {
+            let millis = ttl.as_nanos().div_ceil(1_000_000);
+            cmd.arg("PX").arg(u64::try_from(millis).unwrap_or(u64::MAX));
+        }
+        cmd
     }
 
     /// Pushes an arg to command's arg vec
@@ -61,24 +170,368 @@ impl Command {
         self
     }
 
+    /// Pushes an arg to command's arg vec only if the condition is true
+    pub fn arg_if<T: ToSegmentFrame>(&mut self, cond: bool, arg: T) -> &mut Self {
+        if cond {
+            self.arg(arg);
+        }
+        self
+    }
+
+    /// Pushes the contained value to command's arg vec only if the option is Some
+    pub fn arg_opt<T: ToSegmentFrame>(&mut self, arg: Option<T>) -> &mut Self {
+        if let Some(arg) = arg {
+            self.arg(arg);
+        }
+        self
+    }
+
     /// Constructs a command from the args, executes it and returns the result
     pub async fn query<T: FromSegmentFrame>(
         self,
         connection: &mut Connection,
     ) -> Result<T, CommandError> {
-        let cmd = Frame::Array(self.args);
-        connection.write_frame(&cmd).await?;
+        execute(&self.into_frame(), connection).await
+    }
+
+    /// Constructs a command from the args, executes it and decodes the result with the given coercions in effect.
+    /// `query` allows converting between integers and doubles, while `Coercions::default()` is strict
+    pub async fn query_with<T: FromSegmentFrame>(
+        self,
+        connection: &mut Connection,
+        coercions: Coercions,
+    ) -> Result<T, CommandError> {
+        let response = self.query_frame(connection).await?;
+        coercion::with_coercions(coercions, || decode_response(&response))
+    }
+
+    /// Constructs a command from the args, executes it and decodes only the first `head` elements of the array response,
+    /// e.g. into a tuple. The remaining elements are skipped without being built. Other responses are decoded as is
+    pub async fn query_head<T: FromSegmentFrame>(
+        self,
+        connection: &mut Connection,
+        head: usize,
+    ) -> Result<T, CommandError> {
+        let cmd = self.into_frame();
+        let started = Instant::now();
+        connection
+            .write_frame(&encode(&cmd, connection.dialect()))
+            .await?;
+        let response = connection.read_frame_head(head).await?;
+        log_slow_query(&cmd, connection, started);
+        decode_response(&response)
+    }
+
+    /// Constructs a command from the args, executes it and drives the visitor over the response
+    /// instead of building it, e.g. to aggregate a large array without collecting it
+    pub async fn query_visit<V: FrameVisitor + ?Sized>(
+        self,
+        connection: &mut Connection,
+        visitor: &mut V,
+    ) -> Result<(), CommandError> {
+        let cmd = self.into_frame();
+        let started = Instant::now();
+        connection
+            .write_frame(&encode(&cmd, connection.dialect()))
+            .await?;
+        let result = connection.read_frame_visit(visitor).await;
+        log_slow_query(&cmd, connection, started);
+        result
+    }
+
+    /// Constructs a command from the args, executes it and returns the response frame as is.
+    /// Unlike `query`, error frames are returned instead of being converted to an owned `QueryError`,
+    /// so they can be inspected with `Frame::error_code` and `Frame::error_message` without allocating
+    pub async fn query_frame(self, connection: &mut Connection) -> Result<Frame, CommandError> {
+        let cmd = self.into_frame();
+        let started = Instant::now();
+        connection
+            .write_frame(&encode(&cmd, connection.dialect()))
+            .await?;
         let response = connection.read_frame().await?;
+        log_slow_query(&cmd, connection, started);
+        Ok(response)
+    }
+
+    /// Constructs a command from the args, executes it and decodes the array response into an existing Vec.
+    /// If `clear` is true the Vec is cleared first, otherwise the elements are appended. This allows reusing the
+    /// Vec's allocation across queries. If an element fails to decode, the elements decoded before it are kept
+    pub async fn query_into<T: FromSegmentFrame>(
+        self,
+        connection: &mut Connection,
+        target: &mut Vec<T>,
+        clear: bool,
+    ) -> Result<(), CommandError> {
+        match self.query_frame(connection).await? {
+            Frame::Array(array) => {
+                if clear {
+                    target.clear();
+                }
+                target.reserve(array.len());
+                for v in &array {
+                    target.push(T::from_segment_frame(v)?);
+                }
+                Ok(())
+            }
+            other => Err(incompatible_type::<Vec<T>>(&other)),
+        }
+    }
+
+    /// Marks the command as idempotent, which allows it to be retried after a connection error.
+    /// Commands are not idempotent by default
+    pub fn idempotent(mut self) -> Self {
+        self.idempotent = true;
+        self
+    }
+
+    /// Returns true if the command is marked as idempotent
+    pub fn is_idempotent(&self) -> bool {
+        self.idempotent
+    }
+
+    /// Serializes the command for the given dialect, which must match the dialect of the connections it is sent on
+    pub fn prepare(self, dialect: Dialect) -> PreparedCommand {
+        let idempotent = self.idempotent;
+        let cmd = self.into_frame();
+        let mut bytes = BytesMut::new();
+        frame::serialize(&encode(&cmd, dialect), &mut bytes);
+        PreparedCommand {
+            bytes: bytes.freeze(),
+            idempotent,
+        }
+    }
+
+    pub(crate) fn into_frame(self) -> Frame {
+        Frame::Array(self.args)
+    }
+}
+
+impl PreparedCommand {
+    /// Writes the serialized command and decodes the result
+    pub async fn query<T: FromSegmentFrame>(
+        &self,
+        connection: &mut Connection,
+    ) -> Result<T, CommandError> {
+        connection.write_raw(&self.bytes).await?;
+        decode_response(&connection.read_frame().await?)
+    }
+
+    /// Returns the serialized command
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// Returns true if the command was marked as idempotent before being prepared
+    pub fn is_idempotent(&self) -> bool {
+        self.idempotent
+    }
+}
+
+/// The number of commands a pipeline created with a maximum reserves room for up front
+const PIPELINE_INITIAL_CAPACITY: usize = 16;
+
+impl Pipeline {
+    /// Creates an empty pipeline which holds any number of commands
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an empty pipeline which holds at most `max_commands` commands,
+    /// adding more fails so the commands are sent in batches. The limit is not preallocated,
+    /// the pipeline reserves room for a few commands up front and grows as commands are added
+    pub fn with_capacity(max_commands: usize) -> Self {
+        Pipeline {
+            commands: Vec::with_capacity(max_commands.min(PIPELINE_INITIAL_CAPACITY)),
+            max_commands: Some(max_commands),
+        }
+    }
+
+    /// Adds a command to the pipeline, failing with `CommandError::PipelineFull` if it is full
+    pub fn add(&mut self, cmd: Command) -> Result<&mut Self, CommandError> {
+        if let Some(max_commands) = self.max_commands {
+            if self.commands.len() >= max_commands {
+                return Err(CommandError::PipelineFull(max_commands));
+            }
+        }
+        self.commands.push(cmd);
+        Ok(self)
+    }
+
+    /// Returns the number of commands in the pipeline
+    pub fn len(&self) -> usize {
+        self.commands.len()
+    }
+
+    /// Returns true if the pipeline holds no commands
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Writes all the commands and reads a response for each, see `Connection::query_pipeline`.
+    /// The pipeline is emptied so it can be filled with the next batch
+    pub async fn query(
+        &mut self,
+        connection: &mut Connection,
+    ) -> Result<Vec<Result<Frame, CommandError>>, ConnectionError> {
+        connection
+            .query_pipeline(std::mem::take(&mut self.commands))
+            .await
+    }
+}
+
+pub(crate) async fn execute<T: FromSegmentFrame>(
+    cmd: &Frame,
+    connection: &mut Connection,
+) -> Result<T, CommandError> {
+    let started = Instant::now();
+    connection
+        .write_frame(&encode(cmd, connection.dialect()))
+        .await?;
+    let response = connection.read_frame().await?;
+    log_slow_query(cmd, connection, started);
+    decode_response(&response)
+}
 
-        match response {
-            Frame::Error(val) => Err(CommandError::QueryError(
-                str::from_utf8(&val[..])?.to_string(),
-            )),
-            _ => T::from_segment_frame(&response),
+/// Logs a warning with the command name and the elapsed time if the query took longer than the connection's
+/// slow query threshold. This does nothing unless the tracing feature is enabled
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+fn log_slow_query(cmd: &Frame, connection: &Connection, started: Instant) {
+    #[cfg(feature = "tracing")]
+    if let Some(threshold) = connection.slow_query_threshold() {
+        let elapsed = started.elapsed();
+        if elapsed > threshold {
+            let name = match cmd.iter().next() {
+                Some(Frame::String(name)) => String::from_utf8_lossy(name).into_owned(),
+                _ => String::new(),
+            };
+            tracing::warn!(command = %name, elapsed_ms = elapsed.as_millis() as u64, "slow query");
         }
     }
 }
 
+fn decode_response<T: FromSegmentFrame>(response: &Frame) -> Result<T, CommandError> {
+    match response {
+        Frame::Error(val) => Err(CommandError::QueryError(
+            str::from_utf8(&val[..])?.to_string(),
+        )),
+        _ => T::from_segment_frame(response),
+    }
+}
+
+/// Encodes the command frame according to the dialect
+pub(crate) fn encode(cmd: &Frame, dialect: Dialect) -> Cow<'_, Frame> {
+    match dialect {
+        Dialect::Typed => Cow::Borrowed(cmd),
+        Dialect::BulkStrings => Cow::Owned(bulk_string_args(cmd)),
+    }
+}
+
+/// Returns the error for a frame which can not be decoded into `T`.
+/// Error frames are reported as query errors wherever they are encountered, e.g. inside an array,
+/// and nulls are reported separately so a missing value can be told apart from a value of the wrong type
+pub(crate) fn incompatible_type<T>(frame: &Frame) -> CommandError {
+    match frame {
+        Frame::Error(val) => CommandError::QueryError(String::from_utf8_lossy(val).into_owned()),
+        Frame::Null => CommandError::UnexpectedNull(type_name::<T>()),
+        other => CommandError::IncompatibleType(other.as_str(), type_name::<T>()),
+    }
+}
+
+/// Converts an integer frame value to `T`, failing instead of truncating when it does not fit.
+/// This also guards against platform dependent truncation of `usize` and `isize` on 32 bit targets
+fn checked_integer<T: TryFrom<i64>>(val: i64) -> Result<T, CommandError> {
+    T::try_from(val).map_err(|_| CommandError::OutOfRange(type_name::<T>()))
+}
+
+/// Converts a double frame value to `T` if it holds a whole number. Fractional doubles and NaN are not integers,
+/// while infinite doubles and those beyond the range of `i64` are out of range
+fn checked_double<T: TryFrom<i64>>(val: f64) -> Result<T, CommandError> {
+    // 2^63, the bounds of i64 as doubles are -LIMIT inclusive and LIMIT exclusive
+    const LIMIT: f64 = 9_223_372_036_854_775_808.0;
+    if val.is_nan() || (val.is_finite() && val.fract() != 0.0) {
+        Err(CommandError::IncompatibleType(
+            "frame::Double",
+            type_name::<T>(),
+        ))
+    } else if !(-LIMIT..LIMIT).contains(&val) {
+        Err(CommandError::OutOfRange(type_name::<T>()))
+    } else {
+        checked_integer(val as i64)
+    }
+}
+
+/// Decodes a non integer frame into an integer type if the coercions in effect allow it
+fn coerce_integer<T: TryFrom<i64>>(frame: &Frame) -> Result<T, CommandError> {
+    let coercions = coercion::current();
+    match frame {
+        Frame::Double(val) if coercions.float_to_int() => checked_double(*val),
+        Frame::Boolean(val) if coercions.bool_to_int() => checked_integer(*val as i64),
+        Frame::String(val) if coercions.string_to_number() => checked_integer(
+            str::from_utf8(&val[..])?
+                .parse::<i64>()
+                .map_err(|_| CommandError::Decode)?,
+        ),
+        other => Err(incompatible_type::<T>(other)),
+    }
+}
+
+/// Encodes an unsigned integer as an integer frame, or as a string frame if it is above `i64::MAX`
+fn large_unsigned_frame(val: u64) -> Frame {
+    match i64::try_from(val) {
+        Ok(val) => Frame::Integer(val),
+        Err(_) => Frame::String(Bytes::from(val.to_string())),
+    }
+}
+
+/// Decodes a string frame holding an unsigned integer above `i64::MAX` as encoded by `large_unsigned_frame`,
+/// any other non integer frame is decoded if the coercions in effect allow it
+fn decode_large_unsigned<T: TryFrom<i64> + TryFrom<u64>>(frame: &Frame) -> Result<T, CommandError> {
+    let large = match frame {
+        Frame::String(val) => str::from_utf8(&val[..])
+            .ok()
+            .and_then(|val| val.parse::<u64>().ok())
+            .filter(|val| *val > i64::MAX as u64),
+        _ => None,
+    };
+    match large {
+        Some(val) => T::try_from(val).map_err(|_| CommandError::OutOfRange(type_name::<T>())),
+        None => coerce_integer(frame),
+    }
+}
+
+/// Decodes a non double frame into a floating point type if the coercions in effect allow it
+fn coerce_float<T>(frame: &Frame) -> Result<f64, CommandError> {
+    let coercions = coercion::current();
+    match frame {
+        Frame::Integer(val) if coercions.int_to_float() => Ok(*val as f64),
+        Frame::String(val) if coercions.string_to_number() => str::from_utf8(&val[..])?
+            .parse::<f64>()
+            .map_err(|_| CommandError::Decode),
+        other => Err(incompatible_type::<T>(other)),
+    }
+}
+
+fn bulk_string_args(cmd: &Frame) -> Frame {
+    let args = match cmd {
+        Frame::Array(args) => args,
+        other => return other.clone(),
+    };
+
+    let mut encoded = Vec::with_capacity(args.len());
+    for (idx, arg) in args.iter().enumerate() {
+        encoded.push(match arg {
+            Frame::String(val) if idx == 0 => Frame::String(Bytes::from(val.to_ascii_uppercase())),
+            Frame::Integer(val) => Frame::String(Bytes::from(val.to_string())),
+            Frame::Double(val) => Frame::String(Bytes::from(val.to_string())),
+            Frame::Boolean(val) => Frame::String(Bytes::from(if *val { "1" } else { "0" })),
+            Frame::Null => Frame::String(Bytes::new()),
+            other => other.clone(),
+        });
+    }
+
+    Frame::Array(encoded)
+}
+
 impl Default for Command {
     fn default() -> Self {
         Self::new()
@@ -121,21 +574,23 @@ impl ToSegmentFrame for i32 {
     }
 }
 
+/// Values above `i64::MAX` do not fit an integer frame, they are sent as a string frame holding the decimal value
 impl ToSegmentFrame for u64 {
     fn to_segment_frame(&self) -> Frame {
-        Frame::Integer(*self as i64)
+        large_unsigned_frame(*self)
     }
 }
 
 impl ToSegmentFrame for i64 {
     fn to_segment_frame(&self) -> Frame {
-        Frame::Integer(*self as i64)
+        Frame::Integer(*self)
     }
 }
 
+/// Values above `i64::MAX` do not fit an integer frame, they are sent as a string frame holding the decimal value
 impl ToSegmentFrame for usize {
     fn to_segment_frame(&self) -> Frame {
-        Frame::Integer(*self as i64)
+        large_unsigned_frame(*self as u64)
     }
 }
 
@@ -153,7 +608,7 @@ impl ToSegmentFrame for f32 {
 
 impl ToSegmentFrame for f64 {
     fn to_segment_frame(&self) -> Frame {
-        Frame::Double(*self as f64)
+        Frame::Double(*self)
     }
 }
 
@@ -181,6 +636,24 @@ impl ToSegmentFrame for Bytes {
     }
 }
 
+impl ToSegmentFrame for Arc<[u8]> {
+    fn to_segment_frame(&self) -> Frame {
+        Frame::String(Bytes::copy_from_slice(self))
+    }
+}
+
+impl ToSegmentFrame for Rc<[u8]> {
+    fn to_segment_frame(&self) -> Frame {
+        Frame::String(Bytes::copy_from_slice(self))
+    }
+}
+
+impl ToSegmentFrame for Cow<'_, [u8]> {
+    fn to_segment_frame(&self) -> Frame {
+        Frame::String(Bytes::copy_from_slice(self))
+    }
+}
+
 impl<T: ToSegmentFrame> ToSegmentFrame for Option<T> {
     fn to_segment_frame(&self) -> Frame {
         if let Some(val) = self {
@@ -192,25 +665,74 @@ impl<T: ToSegmentFrame> ToSegmentFrame for Option<T> {
 
 impl<K: ToSegmentFrame, V: ToSegmentFrame> ToSegmentFrame for HashMap<K, V> {
     fn to_segment_frame(&self) -> Frame {
-        let mut map = Vec::with_capacity(2 * self.len());
-        for (key, value) in self.iter() {
-            map.push(key.to_segment_frame());
-            map.push(value.to_segment_frame());
-        }
+        map_frame(self.len(), self.iter())
+    }
+}
+
+impl<K: ToSegmentFrame, V: ToSegmentFrame> ToSegmentFrame for &HashMap<K, V> {
+    fn to_segment_frame(&self) -> Frame {
+        map_frame(self.len(), self.iter())
+    }
+}
+
+/// Encodes the range as a two element array holding its start and its exclusive end
+impl<T: ToSegmentFrame> ToSegmentFrame for Range<T> {
+    fn to_segment_frame(&self) -> Frame {
+        Frame::Array(vec![
+            self.start.to_segment_frame(),
+            self.end.to_segment_frame(),
+        ])
+    }
+}
+
+/// Encodes the range as a two element array holding its start and its inclusive end
+impl<T: ToSegmentFrame> ToSegmentFrame for RangeInclusive<T> {
+    fn to_segment_frame(&self) -> Frame {
+        Frame::Array(vec![
+            self.start().to_segment_frame(),
+            self.end().to_segment_frame(),
+        ])
+    }
+}
+
+/// Encodes the map with its entries in key order
+impl<K: ToSegmentFrame, V: ToSegmentFrame> ToSegmentFrame for BTreeMap<K, V> {
+    fn to_segment_frame(&self) -> Frame {
+        map_frame(self.len(), self.iter())
+    }
+}
+
+impl<K: ToSegmentFrame, V: ToSegmentFrame> ToSegmentFrame for &BTreeMap<K, V> {
+    fn to_segment_frame(&self) -> Frame {
+        map_frame(self.len(), self.iter())
+    }
+}
+
+fn map_frame<'a, K, V>(len: usize, entries: impl Iterator<Item = (&'a K, &'a V)>) -> Frame
+where
+    K: ToSegmentFrame + 'a,
+    V: ToSegmentFrame + 'a,
+{
+    let mut map = Vec::with_capacity(2 * len);
+    for (key, value) in entries {
+        map.push(key.to_segment_frame());
+        map.push(value.to_segment_frame());
+    }
+
+    Frame::Map(map)
+}
 
-        Frame::Map(map)
+impl<T: ToSegmentFrame> ToSegmentFrame for Box<[T]> {
+    fn to_segment_frame(&self) -> Frame {
+        Frame::Array(self.iter().map(T::to_segment_frame).collect())
     }
 }
 
 impl FromSegmentFrame for u8 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as u8),
-            Frame::Double(val) => Ok(*val as u8),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) => checked_integer(*val),
+            other => coerce_integer(other),
         }
     }
 }
@@ -218,12 +740,8 @@ impl FromSegmentFrame for u8 {
 impl FromSegmentFrame for i8 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as i8),
-            Frame::Double(val) => Ok(*val as i8),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) => checked_integer(*val),
+            other => coerce_integer(other),
         }
     }
 }
@@ -231,12 +749,8 @@ impl FromSegmentFrame for i8 {
 impl FromSegmentFrame for u16 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as u16),
-            Frame::Double(val) => Ok(*val as u16),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) => checked_integer(*val),
+            other => coerce_integer(other),
         }
     }
 }
@@ -244,12 +758,8 @@ impl FromSegmentFrame for u16 {
 impl FromSegmentFrame for i16 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as i16),
-            Frame::Double(val) => Ok(*val as i16),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) => checked_integer(*val),
+            other => coerce_integer(other),
         }
     }
 }
@@ -257,12 +767,8 @@ impl FromSegmentFrame for i16 {
 impl FromSegmentFrame for u32 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as u32),
-            Frame::Double(val) => Ok(*val as u32),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) => checked_integer(*val),
+            other => coerce_integer(other),
         }
     }
 }
@@ -270,12 +776,8 @@ impl FromSegmentFrame for u32 {
 impl FromSegmentFrame for i32 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as i32),
-            Frame::Double(val) => Ok(*val as i32),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) => checked_integer(*val),
+            other => coerce_integer(other),
         }
     }
 }
@@ -283,12 +785,8 @@ impl FromSegmentFrame for i32 {
 impl FromSegmentFrame for u64 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as u64),
-            Frame::Double(val) => Ok(*val as u64),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) => checked_integer(*val),
+            other => decode_large_unsigned(other),
         }
     }
 }
@@ -297,11 +795,25 @@ impl FromSegmentFrame for i64 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
             Frame::Integer(val) => Ok(*val),
-            Frame::Double(val) => Ok(*val as i64),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            other => coerce_integer(other),
+        }
+    }
+}
+
+impl FromSegmentFrame for usize {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        match frame {
+            Frame::Integer(val) => checked_integer(*val),
+            other => decode_large_unsigned(other),
+        }
+    }
+}
+
+impl FromSegmentFrame for isize {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        match frame {
+            Frame::Integer(val) => checked_integer(*val),
+            other => coerce_integer(other),
         }
     }
 }
@@ -309,12 +821,8 @@ impl FromSegmentFrame for i64 {
 impl FromSegmentFrame for f32 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as f32),
             Frame::Double(val) => Ok(*val as f32),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            other => coerce_float::<Self>(other).map(|val| val as f32),
         }
     }
 }
@@ -322,12 +830,8 @@ impl FromSegmentFrame for f32 {
 impl FromSegmentFrame for f64 {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
-            Frame::Integer(val) => Ok(*val as f64),
             Frame::Double(val) => Ok(*val),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            other => coerce_float::<Self>(other),
         }
     }
 }
@@ -336,10 +840,21 @@ impl FromSegmentFrame for bool {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
             Frame::Boolean(val) => Ok(*val),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) if coercion::current().int_to_bool() => match val {
+                0 => Ok(false),
+                1 => Ok(true),
+                _ => Err(CommandError::Decode),
+            },
+            Frame::String(val) if coercion::current().string_to_bool() => {
+                if val.eq_ignore_ascii_case(b"true") {
+                    Ok(true)
+                } else if val.eq_ignore_ascii_case(b"false") {
+                    Ok(false)
+                } else {
+                    Err(CommandError::Decode)
+                }
+            }
+            other => Err(incompatible_type::<Self>(other)),
         }
     }
 }
@@ -348,10 +863,9 @@ impl FromSegmentFrame for String {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
             Frame::String(val) => Ok(str::from_utf8(&val[..])?.to_string()),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            Frame::Integer(val) if coercion::current().number_to_string() => Ok(val.to_string()),
+            Frame::Double(val) if coercion::current().number_to_string() => Ok(val.to_string()),
+            other => Err(incompatible_type::<Self>(other)),
         }
     }
 }
@@ -360,10 +874,38 @@ impl FromSegmentFrame for Bytes {
     fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
         match frame {
             Frame::String(val) => Ok(val.clone()),
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            other => Err(incompatible_type::<Self>(other)),
+        }
+    }
+}
+
+/// Decodes a string frame into a mutable buffer. This always copies the payload
+impl FromSegmentFrame for BytesMut {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        match frame {
+            Frame::String(val) => Ok(BytesMut::from(&val[..])),
+            other => Err(incompatible_type::<Self>(other)),
+        }
+    }
+}
+
+/// Keeps the frame as is. This is the untyped fallback for responses which do not fit a single type,
+/// e.g. `Vec<Frame>` decodes an array whose elements have different types. Nested error frames are kept as well
+impl FromSegmentFrame for Frame {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        Ok(frame.clone())
+    }
+}
+
+/// Decodes an acknowledgement which carries no data, i.e. the string frame `OK` or an empty array.
+/// Any other frame fails, a non-empty array with `CommandError::Decode`, so data which was not expected is not dropped
+impl FromSegmentFrame for () {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        match frame {
+            Frame::String(val) if val == "OK" => Ok(()),
+            Frame::Array(array) if array.is_empty() => Ok(()),
+            Frame::Array(_) => Err(CommandError::Decode),
+            other => Err(incompatible_type::<Self>(other)),
         }
     }
 }
@@ -387,10 +929,39 @@ impl<T: FromSegmentFrame> FromSegmentFrame for Vec<T> {
                 }
                 Ok(vec)
             }
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            other => Err(incompatible_type::<Self>(other)),
+        }
+    }
+}
+
+impl<T: FromSegmentFrame> FromSegmentFrame for Box<[T]> {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        match frame {
+            Frame::Array(array) => array
+                .iter()
+                .map(T::from_segment_frame)
+                .collect::<Result<Vec<T>, CommandError>>()
+                .map(Vec::into_boxed_slice),
+            other => Err(incompatible_type::<Self>(other)),
+        }
+    }
+}
+
+/// Decodes an array into a fixed capacity vector without allocating, failing with `CommandError::Decode`
+/// if the array has more than `N` elements
+#[cfg(feature = "arrayvec")]
+impl<T: FromSegmentFrame, const N: usize> FromSegmentFrame for arrayvec::ArrayVec<T, N> {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        match frame {
+            Frame::Array(array) => {
+                let mut vec = arrayvec::ArrayVec::new();
+                for v in array {
+                    vec.try_push(T::from_segment_frame(v)?)
+                        .map_err(|_| CommandError::Decode)?;
+                }
+                Ok(vec)
+            }
+            other => Err(incompatible_type::<Self>(other)),
         }
     }
 }
@@ -424,10 +995,1284 @@ where
 
                 Ok(result)
             }
-            other => Err(CommandError::IncompatibleType(
-                other.as_str(),
-                type_name::<Self>(),
-            )),
+            other => Err(incompatible_type::<Self>(other)),
+        }
+    }
+}
+
+/// Decodes a pair from either a two element array or a map holding exactly one key value pair.
+/// A possibly null entry, e.g. the reply of a pop, decodes into `Option<(K, V)>` where null is `None`
+impl<K: FromSegmentFrame, V: FromSegmentFrame> FromSegmentFrame for (K, V) {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        match frame {
+            Frame::Array(pair) | Frame::Map(pair) => {
+                if pair.len() != 2 {
+                    return Err(CommandError::Decode);
+                }
+                Ok((
+                    K::from_segment_frame(&pair[0])?,
+                    V::from_segment_frame(&pair[1])?,
+                ))
+            }
+            other => Err(incompatible_type::<Self>(other)),
+        }
+    }
+}
+
+/// Encodes the timestamp as an integer frame holding milliseconds since the Unix epoch, the timezone is not encoded
+#[cfg(feature = "chrono")]
+impl<Tz: chrono::TimeZone> ToSegmentFrame for chrono::DateTime<Tz> {
+    fn to_segment_frame(&self) -> Frame {
+        Frame::Integer(self.timestamp_millis())
+    }
+}
+
+/// Decodes an integer frame holding milliseconds since the Unix epoch into a UTC timestamp
+#[cfg(feature = "chrono")]
+impl FromSegmentFrame for chrono::DateTime<chrono::Utc> {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        let millis = match frame {
+            Frame::Null => return Err(CommandError::UnexpectedNull(type_name::<Self>())),
+            other => i64::from_segment_frame(other)?,
+        };
+        chrono::DateTime::from_timestamp_millis(millis)
+            .ok_or(CommandError::OutOfRange(type_name::<Self>()))
+    }
+}
+
+/// Implements `ToSegmentFrame` and `FromSegmentFrame` for a fieldless enum, encoding each variant as a string frame
+/// holding its name, e.g. `impl_segment_enum!(Mode { Fast, Safe })`. Decoding a string which is not one of the
+/// listed names fails with `CommandError::Decode`
+#[macro_export]
+macro_rules! impl_segment_enum {
+    ($ty:ty { $($variant:ident),+ $(,)? }) => {
+        impl $crate::command::ToSegmentFrame for $ty {
+            fn to_segment_frame(&self) -> $crate::frame::Frame {
+                let name = match self {
+                    $(Self::$variant => stringify!($variant),)+
+                };
+                $crate::command::ToSegmentFrame::to_segment_frame(&name)
+            }
+        }
+
+        impl $crate::command::FromSegmentFrame for $ty {
+            fn from_segment_frame(
+                frame: &$crate::frame::Frame,
+            ) -> Result<Self, $crate::command::CommandError> {
+                let index = $crate::command::decode_enum_variant::<Self>(
+                    frame,
+                    &[$(stringify!($variant)),+],
+                )?;
+                Ok([$(Self::$variant),+]
+                    .into_iter()
+                    .nth(index)
+                    .expect("index of a listed variant"))
+            }
+        }
+    };
+}
+
+/// Generates an async function per command which sends the command named after the function with its args,
+/// e.g. `segment_command!(get(key: &str) -> Option<String>)` defines
+/// `async fn get(connection: &mut Connection, key: &str) -> Result<Option<String>, CommandError>`
+/// which sends `GET key` and decodes the reply. Several commands can be defined at once, separated by `;`
+#[macro_export]
+macro_rules! segment_command {
+    ($(
+        $(#[$meta:meta])*
+        $vis:vis $name:ident($($arg:ident: $arg_ty:ty),* $(,)?) -> $ret:ty
+    );+ $(;)?) => {
+        $(
+            $(#[$meta])*
+            $vis async fn $name(
+                connection: &mut $crate::connection::Connection,
+                $($arg: $arg_ty),*
+            ) -> Result<$ret, $crate::command::CommandError> {
+                let mut cmd = $crate::command::Command::new();
+                cmd.arg(stringify!($name).to_uppercase());
+                $(cmd.arg($arg);)*
+                cmd.query::<$ret>(connection).await
+            }
+        )+
+    };
+}
+
+/// Returns the index of the name held by a string frame, used by `impl_segment_enum`
+#[doc(hidden)]
+pub fn decode_enum_variant<T>(frame: &Frame, names: &[&str]) -> Result<usize, CommandError> {
+    match frame {
+        Frame::String(val) => names
+            .iter()
+            .position(|name| val == name.as_bytes())
+            .ok_or(CommandError::Decode),
+        other => Err(incompatible_type::<T>(other)),
+    }
+}
+
+/// Decodes a range from a two element array holding its start and its exclusive end
+impl<T: FromSegmentFrame> FromSegmentFrame for Range<T> {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        let (start, end) = range_bounds(frame)?;
+        Ok(start..end)
+    }
+}
+
+/// Decodes a range from a two element array holding its start and its inclusive end
+impl<T: FromSegmentFrame> FromSegmentFrame for RangeInclusive<T> {
+    fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+        let (start, end) = range_bounds(frame)?;
+        Ok(start..=end)
+    }
+}
+
+fn range_bounds<T: FromSegmentFrame>(frame: &Frame) -> Result<(T, T), CommandError> {
+    match frame {
+        Frame::Array(bounds) if bounds.len() == 2 => Ok((
+            T::from_segment_frame(&bounds[0])?,
+            T::from_segment_frame(&bounds[1])?,
+        )),
+        Frame::Array(_) => Err(CommandError::Decode),
+        other => Err(incompatible_type::<Range<T>>(other)),
+    }
+}
+
+macro_rules! impl_fixed_width_integer {
+    ($($ty:ty),*) => {
+        $(
+            impl ToSegmentFrame for BigEndian<$ty> {
+                fn to_segment_frame(&self) -> Frame {
+                    Frame::String(Bytes::copy_from_slice(&self.0.to_be_bytes()))
+                }
+            }
+
+            impl ToSegmentFrame for LittleEndian<$ty> {
+                fn to_segment_frame(&self) -> Frame {
+                    Frame::String(Bytes::copy_from_slice(&self.0.to_le_bytes()))
+                }
+            }
+
+            impl FromSegmentFrame for BigEndian<$ty> {
+                fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+                    match frame {
+                        Frame::String(val) => Ok(BigEndian(<$ty>::from_be_bytes(
+                            val[..].try_into().map_err(|_| CommandError::Decode)?,
+                        ))),
+                        other => Err(incompatible_type::<Self>(other)),
+                    }
+                }
+            }
+
+            impl FromSegmentFrame for LittleEndian<$ty> {
+                fn from_segment_frame(frame: &Frame) -> Result<Self, CommandError> {
+                    match frame {
+                        Frame::String(val) => Ok(LittleEndian(<$ty>::from_le_bytes(
+                            val[..].try_into().map_err(|_| CommandError::Decode)?,
+                        ))),
+                        other => Err(incompatible_type::<Self>(other)),
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_fixed_width_integer!(u16, i16, u32, i32, u64, i64);
+
+macro_rules! impl_try_from_frame {
+    ($($ty:ty),*) => {
+        $(
+            impl TryFrom<&Frame> for $ty {
+                type Error = CommandError;
+
+                fn try_from(frame: &Frame) -> Result<Self, Self::Error> {
+                    <$ty>::from_segment_frame(frame)
+                }
+            }
+
+            impl TryFrom<Frame> for $ty {
+                type Error = CommandError;
+
+                fn try_from(frame: Frame) -> Result<Self, Self::Error> {
+                    <$ty>::from_segment_frame(&frame)
+                }
+            }
+        )*
+    };
+}
+
+impl_try_from_frame!(
+    u8, i8, u16, i16, u32, i32, u64, i64, usize, isize, f32, f64, bool, String, Bytes
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_server::TestServer;
+
+    #[test]
+    fn from_args_given_slice_pushes_each_arg() {
+        let cmd = Command::from_args(&["GET", "foo"]);
+        assert_eq!(
+            cmd.args,
+            vec![
+                Frame::String(Bytes::from("GET")),
+                Frame::String(Bytes::from("foo")),
+            ]
+        )
+    }
+
+    #[test]
+    fn arg_if_given_true_pushes_arg() {
+        let mut cmd = Command::new();
+        cmd.arg("SET").arg_if(true, "NX");
+        assert_eq!(
+            cmd.args,
+            vec![
+                Frame::String(Bytes::from("SET")),
+                Frame::String(Bytes::from("NX")),
+            ]
+        )
+    }
+
+    #[test]
+    fn arg_if_given_false_does_not_push_arg() {
+        let mut cmd = Command::new();
+        cmd.arg("SET").arg_if(false, "NX");
+        assert_eq!(cmd.args, vec![Frame::String(Bytes::from("SET"))])
+    }
+
+    #[test]
+    fn arg_given_map_reference_keeps_map_usable() {
+        let hash_map = HashMap::from([("foo", 1)]);
+        let btree_map = BTreeMap::from([("b", 2), ("a", 1)]);
+        let mut cmd = Command::new();
+        cmd.arg(&hash_map).arg(&btree_map);
+        assert_eq!(
+            cmd.args,
+            vec![
+                Frame::Map(vec![Frame::String(Bytes::from("foo")), Frame::Integer(1)]),
+                Frame::Map(vec![
+                    Frame::String(Bytes::from("a")),
+                    Frame::Integer(1),
+                    Frame::String(Bytes::from("b")),
+                    Frame::Integer(2),
+                ]),
+            ]
+        );
+        assert_eq!(hash_map["foo"], 1);
+        assert_eq!(btree_map.len(), 2)
+    }
+
+    #[test]
+    fn pipeline_add_given_full_pipeline_returns_pipeline_full() {
+        let mut pipeline = Pipeline::with_capacity(2);
+        pipeline
+            .add(Command::from_args(&["GET", "foo"]))
+            .unwrap()
+            .add(Command::from_args(&["GET", "bar"]))
+            .unwrap();
+        assert!(matches!(
+            pipeline.add(Command::from_args(&["GET", "baz"])),
+            Err(CommandError::PipelineFull(2))
+        ));
+        assert_eq!(pipeline.len(), 2)
+    }
+
+    #[test]
+    fn pipeline_with_capacity_given_large_maximum_does_not_preallocate_it() {
+        let mut pipeline = Pipeline::with_capacity(usize::MAX);
+        assert!(pipeline.commands.capacity() <= PIPELINE_INITIAL_CAPACITY);
+        for _ in 0..100 {
+            pipeline.add(Command::from_args(&["PING"])).unwrap();
+        }
+        assert_eq!(pipeline.len(), 100)
+    }
+
+    #[tokio::test]
+    async fn pipeline_query_returns_responses_and_empties_pipeline() {
+        let server = TestServer::start(|frame| frame).await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+        let mut pipeline = Pipeline::with_capacity(1);
+        pipeline.add(Command::from_args(&["PING"])).unwrap();
+
+        let results = pipeline.query(&mut connection).await.unwrap();
+        assert_eq!(
+            results.into_iter().map(Result::unwrap).collect::<Vec<_>>(),
+            vec![Frame::Array(vec![Frame::String(Bytes::from("PING"))])]
+        );
+        assert!(pipeline.is_empty());
+        pipeline.add(Command::from_args(&["PING"])).unwrap();
+    }
+
+    #[test]
+    fn arg_opt_given_some_pushes_value() {
+        let mut cmd = Command::new();
+        cmd.arg("SET").arg_opt(Some(10));
+        assert_eq!(
+            cmd.args,
+            vec![Frame::String(Bytes::from("SET")), Frame::Integer(10)]
+        )
+    }
+
+    #[test]
+    fn arg_opt_given_none_does_not_push_arg() {
+        let mut cmd = Command::new();
+        cmd.arg("SET").arg_opt(None::<i64>);
+        assert_eq!(cmd.args, vec![Frame::String(Bytes::from("SET"))])
+    }
+
+    #[test]
+    fn try_from_given_compatible_frame_returns_value() {
+        let int: i64 = Frame::Integer(10).try_into().unwrap();
+        let string = String::try_from(&Frame::String(Bytes::from("foo"))).unwrap();
+        assert_eq!(int, 10);
+        assert_eq!(string, "foo")
+    }
+
+    #[test]
+    fn try_from_given_incompatible_frame_returns_incompatible_type_error() {
+        let result: Result<bool, CommandError> = Frame::Integer(1).try_into();
+        assert!(matches!(
+            result,
+            Err(CommandError::IncompatibleType("frame::Integer", "bool"))
+        ))
+    }
+
+    #[test]
+    fn pair_given_two_element_array_returns_pair() {
+        let frame = Frame::Array(vec![Frame::String(Bytes::from("foo")), Frame::Integer(1)]);
+        let pair = <(String, i64)>::from_segment_frame(&frame).unwrap();
+        assert_eq!(pair, ("foo".to_string(), 1))
+    }
+
+    #[test]
+    fn pair_given_single_pair_map_returns_pair() {
+        let frame = Frame::Map(vec![Frame::String(Bytes::from("foo")), Frame::Integer(1)]);
+        let pair = <(String, i64)>::from_segment_frame(&frame).unwrap();
+        assert_eq!(pair, ("foo".to_string(), 1))
+    }
+
+    #[test]
+    fn unit_given_ok_string_or_empty_array_returns_unit() {
+        <()>::from_segment_frame(&Frame::String(Bytes::from("OK"))).unwrap();
+        <()>::from_segment_frame(&Frame::Array(vec![])).unwrap()
+    }
+
+    #[test]
+    fn unit_given_other_frames_returns_error() {
+        assert!(matches!(
+            <()>::from_segment_frame(&Frame::Array(vec![Frame::Integer(1)])),
+            Err(CommandError::Decode)
+        ));
+        assert!(matches!(
+            <()>::from_segment_frame(&Frame::String(Bytes::from("QUEUED"))),
+            Err(CommandError::IncompatibleType("frame::String", "()"))
+        ))
+    }
+
+    #[test]
+    fn vec_of_frames_given_mixed_array_keeps_each_element() {
+        let frame = Frame::Array(vec![
+            Frame::Integer(1),
+            Frame::String(Bytes::from("foo")),
+            Frame::Null,
+        ]);
+        assert_eq!(
+            Vec::<Frame>::from_segment_frame(&frame).unwrap(),
+            vec![
+                Frame::Integer(1),
+                Frame::String(Bytes::from("foo")),
+                Frame::Null
+            ]
+        );
+        assert!(matches!(
+            Vec::<Frame>::from_segment_frame(&Frame::Integer(1)),
+            Err(CommandError::IncompatibleType("frame::Integer", _))
+        ))
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Mode {
+        Fast,
+        Safe,
+        Balanced,
+    }
+
+    crate::impl_segment_enum!(Mode {
+        Fast,
+        Safe,
+        Balanced
+    });
+
+    #[test]
+    fn segment_enum_round_trips_each_variant_by_name() {
+        for (mode, name) in [
+            (Mode::Fast, "Fast"),
+            (Mode::Safe, "Safe"),
+            (Mode::Balanced, "Balanced"),
+        ] {
+            let frame = mode.to_segment_frame();
+            assert_eq!(frame, Frame::String(Bytes::from(name)));
+            assert_eq!(Mode::from_segment_frame(&frame).unwrap(), mode);
+        }
+    }
+
+    #[test]
+    fn segment_enum_given_unknown_name_returns_decode_error() {
+        assert!(matches!(
+            Mode::from_segment_frame(&Frame::String(Bytes::from("fast"))),
+            Err(CommandError::Decode)
+        ));
+        assert!(matches!(
+            Mode::from_segment_frame(&Frame::Null),
+            Err(CommandError::UnexpectedNull(_))
+        ))
+    }
+
+    #[test]
+    fn range_round_trips_as_two_element_array() {
+        let frame = (0..10).to_segment_frame();
+        assert_eq!(
+            frame,
+            Frame::Array(vec![Frame::Integer(0), Frame::Integer(10)])
+        );
+        assert_eq!(Range::<i64>::from_segment_frame(&frame).unwrap(), 0..10);
+
+        let frame = (0..=9).to_segment_frame();
+        assert_eq!(
+            frame,
+            Frame::Array(vec![Frame::Integer(0), Frame::Integer(9)])
+        );
+        assert_eq!(
+            RangeInclusive::<i64>::from_segment_frame(&frame).unwrap(),
+            0..=9
+        )
+    }
+
+    #[test]
+    fn range_given_wrong_length_returns_decode_error() {
+        let frame = Frame::Array(vec![Frame::Integer(0)]);
+        assert!(matches!(
+            Range::<i64>::from_segment_frame(&frame),
+            Err(CommandError::Decode)
+        ))
+    }
+
+    #[test]
+    fn optional_pair_given_null_returns_none() {
+        assert_eq!(
+            Option::<(String, i64)>::from_segment_frame(&Frame::Null).unwrap(),
+            None
+        )
+    }
+
+    #[test]
+    fn optional_pair_given_two_element_array_returns_some_pair() {
+        let frame = Frame::Array(vec![Frame::String(Bytes::from("foo")), Frame::Integer(1)]);
+        assert_eq!(
+            Option::<(String, i64)>::from_segment_frame(&frame).unwrap(),
+            Some(("foo".to_string(), 1))
+        )
+    }
+
+    #[test]
+    fn pair_given_array_with_wrong_length_returns_decode_error() {
+        let frame = Frame::Array(vec![Frame::Integer(1)]);
+        assert!(matches!(
+            <(i64, i64)>::from_segment_frame(&frame),
+            Err(CommandError::Decode)
+        ))
+    }
+
+    #[test]
+    fn pair_given_map_with_multiple_pairs_returns_decode_error() {
+        let frame = Frame::Map(vec![
+            Frame::Integer(1),
+            Frame::Integer(2),
+            Frame::Integer(3),
+            Frame::Integer(4),
+        ]);
+        assert!(matches!(
+            <(i64, i64)>::from_segment_frame(&frame),
+            Err(CommandError::Decode)
+        ))
+    }
+
+    #[test]
+    fn big_endian_u32_round_trips() {
+        let frame = BigEndian(0x01020304u32).to_segment_frame();
+        assert_eq!(frame, Frame::String(Bytes::from_static(&[1, 2, 3, 4])));
+        assert_eq!(
+            BigEndian::<u32>::from_segment_frame(&frame).unwrap(),
+            BigEndian(0x01020304)
+        )
+    }
+
+    #[test]
+    fn little_endian_u32_round_trips() {
+        let frame = LittleEndian(0x01020304u32).to_segment_frame();
+        assert_eq!(frame, Frame::String(Bytes::from_static(&[4, 3, 2, 1])));
+        assert_eq!(
+            LittleEndian::<u32>::from_segment_frame(&frame).unwrap(),
+            LittleEndian(0x01020304)
+        )
+    }
+
+    #[test]
+    fn big_endian_given_wrong_width_returns_decode_error() {
+        let frame = Frame::String(Bytes::from_static(&[1, 2]));
+        assert!(matches!(
+            BigEndian::<u32>::from_segment_frame(&frame),
+            Err(CommandError::Decode)
+        ))
+    }
+
+    #[test]
+    fn idempotent_marks_command_as_idempotent() {
+        assert!(!Command::new().is_idempotent());
+        assert!(Command::new().idempotent().is_idempotent())
+    }
+
+    #[test]
+    fn bytes_mut_given_string_returns_mutable_copy() {
+        let frame = Frame::String(Bytes::from("foo"));
+        let mut buf = BytesMut::from_segment_frame(&frame).unwrap();
+        buf[0] = b'b';
+        buf.extend_from_slice(b"bar");
+        assert_eq!(&buf[..], b"boobar");
+        assert_eq!(frame, Frame::String(Bytes::from("foo")))
+    }
+
+    #[test]
+    fn vec_given_error_element_returns_query_error() {
+        let frame = Frame::Array(vec![
+            Frame::Integer(1),
+            Frame::Error(Bytes::from("ERR foo")),
+        ]);
+        assert!(matches!(
+            Vec::<i64>::from_segment_frame(&frame),
+            Err(CommandError::QueryError(msg)) if msg == "ERR foo"
+        ))
+    }
+
+    #[test]
+    fn boxed_slice_round_trips_through_array_frame() {
+        let data: Box<[i64]> = Box::new([1, -2, 3]);
+        let frame = data.to_segment_frame();
+        assert_eq!(
+            frame,
+            Frame::Array(vec![
+                Frame::Integer(1),
+                Frame::Integer(-2),
+                Frame::Integer(3)
+            ])
+        );
+        assert_eq!(Box::<[i64]>::from_segment_frame(&frame).unwrap(), data)
+    }
+
+    #[test]
+    fn arc_slice_returns_string_frame() {
+        let data: Arc<[u8]> = Arc::from(&b"foo\r\n"[..]);
+        let mut cmd = Command::new();
+        cmd.arg(data.clone());
+        assert_eq!(cmd.args, vec![Frame::String(Bytes::from("foo\r\n"))]);
+        assert_eq!(&data[..], b"foo\r\n")
+    }
+
+    #[test]
+    fn rc_and_cow_slices_return_string_frames() {
+        let rc: Rc<[u8]> = Rc::from(&b"foo"[..]);
+        let cow: Cow<[u8]> = Cow::Borrowed(b"bar");
+        assert_eq!(rc.to_segment_frame(), Frame::String(Bytes::from("foo")));
+        assert_eq!(cow.to_segment_frame(), Frame::String(Bytes::from("bar")))
+    }
+
+    #[test]
+    fn u32_given_integer_beyond_32_bit_range_returns_out_of_range_error() {
+        let frame = Frame::Integer(u32::MAX as i64 + 1);
+        assert!(matches!(
+            u32::from_segment_frame(&frame),
+            Err(CommandError::OutOfRange("u32"))
+        ))
+    }
+
+    #[test]
+    fn u64_above_i64_max_round_trips_through_string_frame() {
+        let frame = u64::MAX.to_segment_frame();
+        assert_eq!(frame, Frame::String(Bytes::from("18446744073709551615")));
+        assert_eq!(u64::from_segment_frame(&frame).unwrap(), u64::MAX);
+        assert_eq!(
+            (i64::MAX as u64).to_segment_frame(),
+            Frame::Integer(i64::MAX)
+        );
+        assert!(matches!(
+            u32::from_segment_frame(&frame),
+            Err(CommandError::IncompatibleType("frame::String", "u32"))
+        ))
+    }
+
+    #[test]
+    fn u64_given_negative_integer_returns_out_of_range_error() {
+        assert!(matches!(
+            u64::from_segment_frame(&Frame::Integer(-1)),
+            Err(CommandError::OutOfRange("u64"))
+        ))
+    }
+
+    #[test]
+    fn usize_given_integer_in_range_returns_value() {
+        assert_eq!(usize::from_segment_frame(&Frame::Integer(10)).unwrap(), 10)
+    }
+
+    #[tokio::test]
+    async fn query_frame_given_error_reply_returns_error_frame() {
+        let server = TestServer::start(|_| Frame::Error(Bytes::from("ERR not found"))).await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        let mut cmd = Command::new();
+        cmd.arg("GET").arg("foo");
+        let frame = cmd.query_frame(&mut connection).await.unwrap();
+        assert_eq!(frame.error_code(), Some(&b"ERR"[..]))
+    }
+
+    #[test]
+    fn i64_given_null_returns_unexpected_null_error() {
+        assert!(matches!(
+            i64::from_segment_frame(&Frame::Null),
+            Err(CommandError::UnexpectedNull("i64"))
+        ))
+    }
+
+    #[test]
+    fn string_given_null_returns_unexpected_null_error() {
+        assert!(matches!(
+            String::from_segment_frame(&Frame::Null),
+            Err(CommandError::UnexpectedNull(_))
+        ))
+    }
+
+    #[test]
+    fn option_given_null_returns_none() {
+        assert_eq!(
+            Option::<i64>::from_segment_frame(&Frame::Null).unwrap(),
+            None
+        )
+    }
+
+    #[tokio::test]
+    async fn query_into_reuses_vec_across_queries() {
+        let server =
+            TestServer::start(|_| Frame::Array(vec![Frame::Integer(1), Frame::Integer(2)])).await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+        let mut values: Vec<i64> = Vec::new();
+
+        Command::from_args(&["LIST"])
+            .query_into(&mut connection, &mut values, false)
+            .await
+            .unwrap();
+        Command::from_args(&["LIST"])
+            .query_into(&mut connection, &mut values, false)
+            .await
+            .unwrap();
+        assert_eq!(values, vec![1, 2, 1, 2]);
+
+        Command::from_args(&["LIST"])
+            .query_into(&mut connection, &mut values, true)
+            .await
+            .unwrap();
+        assert_eq!(values, vec![1, 2])
+    }
+
+    #[tokio::test]
+    async fn query_into_given_error_reply_returns_query_error() {
+        let server = TestServer::start(|_| Frame::Error(Bytes::from("ERR foo"))).await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+        let mut values: Vec<i64> = vec![1];
+
+        let result = Command::from_args(&["LIST"])
+            .query_into(&mut connection, &mut values, true)
+            .await;
+        assert!(matches!(result, Err(CommandError::QueryError(msg)) if msg == "ERR foo"));
+        assert_eq!(values, vec![1])
+    }
+
+    #[test]
+    fn prepare_serializes_command_frame() {
+        let mut cmd = Command::new();
+        cmd.arg("SET").arg("foo").arg(1);
+        let prepared = cmd.idempotent().prepare(Dialect::Typed);
+        assert_eq!(
+            &prepared.bytes()[..],
+            b"*3\r\n$3\r\nSET\r\n$3\r\nfoo\r\n%1\r\n"
+        );
+        assert!(prepared.is_idempotent())
+    }
+
+    #[test]
+    fn encode_given_bulk_strings_dialect_encodes_null_as_empty_string() {
+        let cmd = Frame::Array(vec![Frame::String(Bytes::from("set")), Frame::Null]);
+        assert_eq!(
+            encode(&cmd, Dialect::BulkStrings).into_owned(),
+            Frame::Array(vec![
+                Frame::String(Bytes::from("SET")),
+                Frame::String(Bytes::new())
+            ])
+        )
+    }
+
+    #[test]
+    fn prepare_given_bulk_strings_dialect_encodes_args_as_strings() {
+        let prepared = Command::from_args(&[1, 2]).prepare(Dialect::BulkStrings);
+        let mut expected = BytesMut::new();
+        frame::serialize(
+            &Frame::Array(vec![
+                Frame::String(Bytes::from("1")),
+                Frame::String(Bytes::from("2")),
+            ]),
+            &mut expected,
+        );
+        assert_eq!(prepared.bytes(), &expected)
+    }
+
+    #[tokio::test]
+    async fn prepared_query_can_be_sent_repeatedly() {
+        let server = TestServer::start(|frame| match frame {
+            Frame::Array(args) => args[1].clone(),
+            other => other,
+        })
+        .await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+        let prepared = Command::from_args(&["ECHO", "foo"]).prepare(connection.dialect());
+
+        for _ in 0..3 {
+            let reply: String = prepared.query(&mut connection).await.unwrap();
+            assert_eq!(reply, "foo")
+        }
+    }
+
+    crate::segment_command! {
+        echo(message: &str) -> String;
+        incrby(key: &str, increment: i64) -> i64;
+    }
+
+    #[tokio::test]
+    async fn segment_command_sends_command_named_after_function() {
+        let server = TestServer::start(|frame| match frame {
+            Frame::Array(args) if args[0] == Frame::String(Bytes::from("ECHO")) => args[1].clone(),
+            Frame::Array(args) if args[0] == Frame::String(Bytes::from("INCRBY")) => {
+                args[2].clone()
+            }
+            other => Frame::Error(Bytes::from(format!("ERR unexpected {:?}", other))),
+        })
+        .await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        assert_eq!(echo(&mut connection, "foo").await.unwrap(), "foo");
+        assert_eq!(incrby(&mut connection, "foo", 2).await.unwrap(), 2)
+    }
+
+    #[derive(Default)]
+    struct Sum(i64);
+
+    impl FrameVisitor for Sum {
+        fn visit_integer(&mut self, val: i64) -> Result<(), CommandError> {
+            self.0 += val;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn query_visit_given_integer_array_sums_elements() {
+        let server =
+            TestServer::start(|_| Frame::Array((1..=1000).map(Frame::Integer).collect())).await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        let mut sum = Sum::default();
+        Command::from_args(&["LRANGE", "foo", "0", "-1"])
+            .query_visit(&mut connection, &mut sum)
+            .await
+            .unwrap();
+        assert_eq!(sum.0, 500_500)
+    }
+
+    #[tokio::test]
+    async fn query_visit_given_error_reply_returns_query_error() {
+        let server = TestServer::start(|_| Frame::Error(Bytes::from("ERR foo"))).await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        assert!(matches!(
+            Command::from_args(&["GET", "foo"])
+                .query_visit(&mut connection, &mut Sum::default())
+                .await,
+            Err(CommandError::QueryError(msg)) if msg == "ERR foo"
+        ))
+    }
+
+    #[tokio::test]
+    async fn query_visit_given_unexpected_element_keeps_connection_usable() {
+        let server = TestServer::start(|frame| match frame {
+            Frame::Array(args) if args.len() == 1 => {
+                Frame::Array(vec![Frame::Integer(1), Frame::String(Bytes::from("foo"))])
+            }
+            _ => Frame::Array(vec![Frame::Integer(2)]),
+        })
+        .await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        let mut sum = Sum::default();
+        assert!(Command::from_args(&["FOO"])
+            .query_visit(&mut connection, &mut sum)
+            .await
+            .is_err());
+        let mut sum = Sum::default();
+        Command::from_args(&["FOO", "BAR"])
+            .query_visit(&mut connection, &mut sum)
+            .await
+            .unwrap();
+        assert_eq!(sum.0, 2)
+    }
+
+    #[cfg(feature = "arrayvec")]
+    #[test]
+    fn array_vec_given_fitting_array_returns_elements() {
+        let frame = Frame::Array(vec![Frame::Integer(1), Frame::Integer(2)]);
+        let vec = arrayvec::ArrayVec::<i64, 2>::from_segment_frame(&frame).unwrap();
+        assert_eq!(&vec[..], &[1, 2])
+    }
+
+    #[cfg(feature = "arrayvec")]
+    #[test]
+    fn array_vec_given_overflowing_array_returns_decode_error() {
+        let frame = Frame::Array(vec![
+            Frame::Integer(1),
+            Frame::Integer(2),
+            Frame::Integer(3),
+        ]);
+        assert!(matches!(
+            arrayvec::ArrayVec::<i64, 2>::from_segment_frame(&frame),
+            Err(CommandError::Decode)
+        ))
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn date_time_given_known_timestamp_round_trips_as_millis() {
+        use chrono::{DateTime, FixedOffset, TimeZone, Utc};
+
+        let timestamp = Utc.with_ymd_and_hms(2023, 11, 14, 22, 13, 20).unwrap()
+            + chrono::Duration::milliseconds(123);
+        let frame = timestamp.to_segment_frame();
+        assert_eq!(frame, Frame::Integer(1_700_000_000_123));
+        assert_eq!(
+            DateTime::<Utc>::from_segment_frame(&frame).unwrap(),
+            timestamp
+        );
+
+        let offset = FixedOffset::east_opt(2 * 3600).unwrap();
+        let local = timestamp.with_timezone(&offset);
+        assert_eq!(
+            DateTime::<Utc>::from_segment_frame(&local.to_segment_frame()).unwrap(),
+            timestamp
+        );
+    }
+
+    #[cfg(feature = "chrono")]
+    #[test]
+    fn date_time_given_out_of_range_timestamp_returns_out_of_range_error() {
+        assert!(matches!(
+            chrono::DateTime::<chrono::Utc>::from_segment_frame(&Frame::Integer(i64::MAX)),
+            Err(CommandError::OutOfRange(_))
+        ));
+        assert!(matches!(
+            chrono::DateTime::<chrono::Utc>::from_segment_frame(&Frame::Null),
+            Err(CommandError::UnexpectedNull(_))
+        ))
+    }
+
+    #[test]
+    fn from_parse_frame_error_maps_each_kind() {
+        assert!(matches!(
+            CommandError::from(ParseFrameError::Incomplete),
+            CommandError::IncompleteFrame
+        ));
+        assert!(matches!(
+            CommandError::from(ParseFrameError::InvalidFormat {
+                context: "integer",
+                offset: 4
+            }),
+            CommandError::InvalidFrame {
+                context: "integer",
+                offset: 4
+            }
+        ));
+        assert!(matches!(
+            CommandError::from(ConnectionError::FrameError(ParseFrameError::FrameTooLarge)),
+            CommandError::FrameTooLarge
+        ));
+        assert!(matches!(
+            CommandError::from(ConnectionError::Eof),
+            CommandError::ConnectionError(ConnectionError::Eof)
+        ))
+    }
+
+    #[tokio::test]
+    async fn query_given_reply_over_max_frame_size_returns_frame_too_large() {
+        let server = TestServer::start(|_| Frame::String(Bytes::from(vec![b'a'; 1024]))).await;
+        let mut options = server.options();
+        options.set_max_frame_size(64);
+        let mut connection = Connection::connect(&options).await.unwrap();
+
+        assert!(matches!(
+            Command::from_args(&["GET", "foo"])
+                .query::<String>(&mut connection)
+                .await,
+            Err(CommandError::FrameTooLarge)
+        ))
+    }
+
+    /// Collects the fields of every event as text
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct CaptureEvents(Arc<std::sync::Mutex<Vec<String>>>);
+
+    #[cfg(feature = "tracing")]
+    impl tracing::Subscriber for CaptureEvents {
+        fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &tracing::span::Attributes<'_>) -> tracing::span::Id {
+            tracing::span::Id::from_u64(1)
+        }
+
+        fn record(&self, _: &tracing::span::Id, _: &tracing::span::Record<'_>) {}
+
+        fn record_follows_from(&self, _: &tracing::span::Id, _: &tracing::span::Id) {}
+
+        fn event(&self, event: &tracing::Event<'_>) {
+            struct Fields(String);
+
+            impl tracing::field::Visit for Fields {
+                fn record_debug(
+                    &mut self,
+                    field: &tracing::field::Field,
+                    value: &dyn std::fmt::Debug,
+                ) {
+                    self.0.push_str(&format!("{}={:?} ", field.name(), value));
+                }
+            }
+
+            let mut fields = Fields(String::new());
+            event.record(&mut fields);
+            self.0.lock().unwrap().push(fields.0);
+        }
+
+        fn enter(&self, _: &tracing::span::Id) {}
+
+        fn exit(&self, _: &tracing::span::Id) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    #[tokio::test]
+    async fn query_given_slow_reply_logs_slow_query() {
+        let server = TestServer::start_delayed(Duration::from_millis(20), |frame| frame).await;
+        let mut options = server.options();
+        options.set_slow_query_threshold(Duration::from_millis(10));
+        let mut connection = Connection::connect(&options).await.unwrap();
+        let events = CaptureEvents::default();
+        let _guard = tracing::subscriber::set_default(events.clone());
+
+        let _: Vec<String> = Command::from_args(&["PING"])
+            .query(&mut connection)
+            .await
+            .unwrap();
+        connection.set_slow_query_threshold(Some(Duration::from_secs(60)));
+        let _: Vec<String> = Command::from_args(&["ECHO"])
+            .query(&mut connection)
+            .await
+            .unwrap();
+
+        let events = events.0.lock().unwrap();
+        assert_eq!(events.len(), 1);
+        assert!(events[0].contains("message=slow query"));
+        assert!(events[0].contains("command=PING"))
+    }
+
+    #[tokio::test]
+    async fn query_head_given_large_array_decodes_first_two_elements() {
+        let server = TestServer::start(|_| {
+            Frame::Array(
+                (0..10_000)
+                    .map(|i| Frame::String(Bytes::from(format!("item:{}", i))))
+                    .collect(),
+            )
+        })
+        .await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        let head: (String, String) = Command::from_args(&["LRANGE", "foo", "0", "-1"])
+            .query_head(&mut connection, 2)
+            .await
+            .unwrap();
+        assert_eq!(head, ("item:0".to_string(), "item:1".to_string()));
+
+        // The skipped elements are consumed, so the next reply is read from the start
+        let next = Command::from_args(&["LRANGE", "foo", "0", "-1"])
+            .query_frame(&mut connection)
+            .await
+            .unwrap();
+        assert_eq!(next.len(), Some(10_000))
+    }
+
+    #[tokio::test]
+    async fn query_with_toggles_int_to_float_coercion() {
+        let server = TestServer::start(|_| Frame::Integer(10)).await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        let strict = Command::from_args(&["GET", "foo"])
+            .query_with::<f64>(&mut connection, Coercions::default())
+            .await;
+        assert!(matches!(
+            strict,
+            Err(CommandError::IncompatibleType("frame::Integer", "f64"))
+        ));
+
+        let mut coercions = Coercions::new();
+        coercions.set_int_to_float(true);
+        let lenient = Command::from_args(&["GET", "foo"])
+            .query_with::<f64>(&mut connection, coercions)
+            .await;
+        assert_eq!(lenient.unwrap(), 10.0)
+    }
+
+    #[test]
+    fn with_coercions_given_string_to_number_parses_string() {
+        let mut coercions = Coercions::new();
+        coercions.set_string_to_number(true);
+        let frame = Frame::String(Bytes::from("42"));
+        assert_eq!(
+            coercion::with_coercions(coercions, || i64::from_segment_frame(&frame)).unwrap(),
+            42
+        );
+        assert!(i64::from_segment_frame(&frame).is_err())
+    }
+
+    #[test]
+    fn with_coercions_given_int_to_bool_decodes_zero_and_one() {
+        let mut coercions = Coercions::new();
+        coercions.set_int_to_bool(true);
+        coercion::with_coercions(coercions, || {
+            assert!(bool::from_segment_frame(&Frame::Integer(1)).unwrap());
+            assert!(!bool::from_segment_frame(&Frame::Integer(0)).unwrap());
+            assert!(bool::from_segment_frame(&Frame::Integer(2)).is_err())
+        })
+    }
+
+    #[test]
+    fn with_coercions_given_string_to_bool_decodes_textual_booleans() {
+        let mut coercions = Coercions::new();
+        coercions.set_string_to_bool(true);
+        coercion::with_coercions(coercions, || {
+            assert!(bool::from_segment_frame(&Frame::String(Bytes::from("TRUE"))).unwrap());
+            assert!(!bool::from_segment_frame(&Frame::String(Bytes::from("false"))).unwrap());
+            assert!(matches!(
+                bool::from_segment_frame(&Frame::String(Bytes::from("yes"))),
+                Err(CommandError::Decode)
+            ));
+        });
+        assert!(matches!(
+            bool::from_segment_frame(&Frame::String(Bytes::from("true"))),
+            Err(CommandError::IncompatibleType("frame::String", "bool"))
+        ))
+    }
+
+    #[test]
+    fn from_segment_frame_without_coercions_converts_between_integers_and_doubles() {
+        assert_eq!(f64::from_segment_frame(&Frame::Integer(1)).unwrap(), 1.0);
+        assert_eq!(i64::from_segment_frame(&Frame::Double(2.0)).unwrap(), 2)
+    }
+
+    #[test]
+    fn from_segment_frame_given_fractional_or_nan_double_fails_for_integer() {
+        for val in [1.5, -0.5, f64::NAN] {
+            assert!(matches!(
+                i64::from_segment_frame(&Frame::Double(val)),
+                Err(CommandError::IncompatibleType("frame::Double", "i64"))
+            ));
+        }
+    }
+
+    #[test]
+    fn from_segment_frame_given_infinite_or_huge_double_fails_for_integer() {
+        for val in [
+            f64::INFINITY,
+            f64::NEG_INFINITY,
+            1e19,
+            9_223_372_036_854_775_808.0,
+        ] {
+            assert!(matches!(
+                i64::from_segment_frame(&Frame::Double(val)),
+                Err(CommandError::OutOfRange("i64"))
+            ));
+        }
+        assert_eq!(
+            i64::from_segment_frame(&Frame::Double(-9_223_372_036_854_775_808.0)).unwrap(),
+            i64::MIN
+        );
+        assert!(matches!(
+            u8::from_segment_frame(&Frame::Double(256.0)),
+            Err(CommandError::OutOfRange("u8"))
+        ))
+    }
+
+    #[test]
+    fn set_ex_given_whole_seconds_uses_ex() {
+        let cmd = Command::set_ex("foo", "bar", Duration::from_secs(10));
+        assert_eq!(
+            cmd.args,
+            vec![
+                Frame::String(Bytes::from("SET")),
+                Frame::String(Bytes::from("foo")),
+                Frame::String(Bytes::from("bar")),
+                Frame::String(Bytes::from("EX")),
+                Frame::Integer(10),
+            ]
+        )
+    }
+
+    #[test]
+    fn set_ex_given_sub_second_precision_uses_px() {
+        let cmd = Command::set_ex("foo", 1, Duration::from_millis(1500));
+        assert_eq!(
+            cmd.args,
+            vec![
+                Frame::String(Bytes::from("SET")),
+                Frame::String(Bytes::from("foo")),
+                Frame::Integer(1),
+                Frame::String(Bytes::from("PX")),
+                Frame::Integer(1500),
+            ]
+        )
+    }
+
+    #[test]
+    fn set_ex_given_sub_millisecond_ttl_rounds_up_to_one_millisecond() {
+        let cmd = Command::set_ex("foo", 1, Duration::from_micros(500));
+        assert_eq!(
+            &cmd.args[3..],
+            [Frame::String(Bytes::from("PX")), Frame::Integer(1)]
+        )
+    }
+
+    #[test]
+    fn set_ex_given_zero_ttl_sends_zero_seconds() {
+        let cmd = Command::set_ex("foo", 1, Duration::ZERO);
+        assert_eq!(
+            &cmd.args[3..],
+            [Frame::String(Bytes::from("EX")), Frame::Integer(0)]
+        )
+    }
+
+    #[test]
+    fn set_ex_given_ttl_beyond_u64_millis_saturates() {
+        let cmd = Command::set_ex("foo", 1, Duration::new(u64::MAX, 1));
+        assert_eq!(cmd.args[4], u64::MAX.to_segment_frame())
+    }
+
+    mod round_trip {
+        use super::*;
+        use crate::strategies::{self, round_trip};
+        use proptest::collection::{hash_map, vec};
+        use proptest::prelude::*;
+
+        proptest! {
+            #[test]
+            fn integers_round_trip(
+                a in any::<u8>(),
+                b in any::<i8>(),
+                c in any::<u16>(),
+                d in any::<i16>(),
+                e in any::<u32>(),
+                f in any::<i32>(),
+                g in any::<i64>(),
+                h in any::<isize>(),
+            ) {
+                prop_assert_eq!(round_trip(&a)?, a);
+                prop_assert_eq!(round_trip(&b)?, b);
+                prop_assert_eq!(round_trip(&c)?, c);
+                prop_assert_eq!(round_trip(&d)?, d);
+                prop_assert_eq!(round_trip(&e)?, e);
+                prop_assert_eq!(round_trip(&f)?, f);
+                prop_assert_eq!(round_trip(&g)?, g);
+                prop_assert_eq!(round_trip(&h)?, h);
+            }
+
+            // Unsigned 64 bit values above i64::MAX are sent as string frames
+            #[test]
+            fn unsigned_64_bit_integers_round_trip(
+                a in any::<u64>(),
+                b in any::<usize>(),
+            ) {
+                prop_assert_eq!(round_trip(&a)?, a);
+                prop_assert_eq!(round_trip(&b)?, b);
+            }
+
+            #[test]
+            fn floats_round_trip(
+                a in any::<f32>().prop_filter("NaN is never equal to itself", |val| !val.is_nan()),
+                b in strategies::double(),
+            ) {
+                prop_assert_eq!(round_trip(&a)?, a);
+                prop_assert_eq!(round_trip(&b)?, b);
+            }
+
+            #[test]
+            fn bool_round_trips(val in any::<bool>()) {
+                prop_assert_eq!(round_trip(&val)?, val);
+            }
+
+            #[test]
+            fn strings_round_trip(val in ".*") {
+                prop_assert_eq!(round_trip(&val)?, val);
+            }
+
+            #[test]
+            fn bytes_round_trip(val in strategies::bytes()) {
+                prop_assert_eq!(round_trip(&val)?, val);
+            }
+
+            #[test]
+            fn options_round_trip(val in proptest::option::of(any::<i64>())) {
+                prop_assert_eq!(round_trip(&val)?, val);
+            }
+
+            #[test]
+            fn maps_round_trip(val in hash_map(".*", any::<i64>(), 0..16)) {
+                prop_assert_eq!(round_trip(&val)?, val);
+            }
+
+            #[test]
+            fn nested_maps_round_trip(
+                val in hash_map(".*", hash_map(strategies::bytes(), proptest::option::of(".*"), 0..4), 0..4),
+            ) {
+                prop_assert_eq!(round_trip(&val)?, val);
+            }
+
+            #[test]
+            fn boxed_slices_round_trip(val in vec(any::<i64>(), 0..16)) {
+                let val = val.into_boxed_slice();
+                prop_assert_eq!(round_trip(&val)?, val);
+            }
+
+            #[test]
+            fn arrays_decode_into_vecs(val in vec(vec(strategies::bytes(), 0..8), 0..8)) {
+                let frame = Frame::Array(
+                    val.iter()
+                        .map(|inner| Frame::Array(inner.iter().map(ToSegmentFrame::to_segment_frame).collect()))
+                        .collect(),
+                );
+                prop_assert_eq!(Vec::<Vec<Bytes>>::from_segment_frame(&strategies::reparse(&frame))?, val);
+            }
         }
     }
 }
@@ -1,25 +1,88 @@
-use crate::frame::{
-    self, Frame, ParseFrameError, ARRAY_IDENT, BOOLEAN_IDENT, DOUBLE_IDENT, ERROR_IDENT,
-    INTEGER_IDENT, MAP_IDENT, STRING_IDENT,
-};
-use bytes::{Buf, BytesMut};
+use crate::command::{self, Command, CommandError, FromSegmentFrame, ToSegmentFrame};
+use crate::frame::{self, Frame, FrameLimits, FrameVisitor, ParseFrameError, ATTRIBUTE_IDENT};
+use crate::resolver::Resolver;
+use crate::runtime::{Runtime, Stream};
+#[cfg(feature = "socks")]
+use crate::socks::Socks5Proxy;
+use bytes::{Buf, Bytes, BytesMut};
 use std::io::{self, Cursor};
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
-use tokio::net::TcpStream;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Default host used by `ConnectionOptions::default`
+pub const DEFAULT_HOST: &str = "127.0.0.1";
+/// Default port of the Segment server
+pub const DEFAULT_PORT: u16 = 1698;
 
 /// Represents connection option
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ConnectionOptions {
     host: String,
     port: u16,
+    max_buffer_growth: Option<usize>,
+    resolver: Option<Arc<dyn Resolver>>,
+    #[cfg(feature = "socks")]
+    socks5_proxy: Option<Socks5Proxy>,
+    dialect: Dialect,
+    bind_addr: Option<SocketAddr>,
+    max_frame_size: Option<usize>,
+    max_elements: Option<usize>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    slow_query_threshold: Option<Duration>,
+    runtime: Runtime,
 }
 
 #[derive(Debug)]
 /// Represents a Segment connection
 pub struct Connection {
-    stream: TcpStream,
+    stream: Stream,
+    runtime: Runtime,
     buf: BytesMut,
+    max_buffer_growth: Option<usize>,
+    dialect: Dialect,
+    attributes: Option<Vec<Frame>>,
+    // Attributes read ahead of a reply which is still incomplete, kept across timeouts until the reply arrives
+    pending_attributes: Option<Vec<Frame>>,
+    limits: FrameLimits,
+    last_used: Instant,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    pipeline_guard: PipelineGuard,
+    pending_responses: usize,
+    bytes_written: u64,
+    slow_query_threshold: Option<Duration>,
+    closed: bool,
+    pending_error: Option<ConnectionError>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Represents how a connection checks that every frame written is matched by exactly one response read,
+/// which catches manual pipelining reading the response of a later command by mistake
+pub enum PipelineGuard {
+    /// Reads and writes are not tracked
+    #[default]
+    Off,
+    /// Reading a response when no written frame is awaiting one fails
+    Counted,
+    /// Like `Counted`, and writing a frame while a response is unread fails as well,
+    /// so commands must be sent one at a time
+    Strict,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+/// Represents how commands are encoded before being sent to the server
+pub enum Dialect {
+    /// Arguments are sent using the frame type they were converted to
+    #[default]
+    Typed,
+
+    /// The command name is uppercased and every scalar argument is sent as a string frame, null as an empty string
+    BulkStrings,
 }
 
 #[derive(Debug, Error)]
@@ -29,6 +92,18 @@ pub enum ConnectionError {
     #[error(transparent)]
     TCPError(#[from] io::Error),
 
+    /// Occurs when the host can not be resolved to any address
+    #[error("failed to resolve host: {0}")]
+    DnsFailure(io::Error),
+
+    /// Occurs when every resolved address refuses the connection, e.g. because nothing listens on the port
+    #[error("connection refused: {0}")]
+    ConnectionRefused(io::Error),
+
+    /// Occurs when connecting times out before the server accepts the connection
+    #[error("connect timed out: {0}")]
+    ConnectTimeout(io::Error),
+
     /// Occurs when the connection is prematurely closed by the server
     #[error("server did not send any response")]
     Eof,
@@ -36,34 +111,487 @@ pub enum ConnectionError {
     /// Occurs when there is an error in parsing the frame
     #[error(transparent)]
     FrameError(#[from] ParseFrameError),
+
+    /// Occurs when a read or write does not complete within the configured timeout
+    #[error("operation timed out")]
+    Timeout,
+
+    /// Occurs when the connection options are invalid
+    #[error(transparent)]
+    ConfigError(#[from] ConfigError),
+
+    /// Occurs when the pipeline guard is enabled and a response is read although every written frame has been answered
+    #[error("attempted to read a response with no outstanding request")]
+    UnexpectedRead,
+
+    /// Occurs when writing a map or attribute frame with an odd number of elements, which would desync the server
+    #[error("map frame has an odd number of elements")]
+    UnpairedMapEntry,
+
+    /// Occurs when the SOCKS5 proxy rejects the handshake or fails to connect to the host
+    #[error("proxy error: {0}")]
+    ProxyError(String),
+
+    /// Occurs when the strict pipeline guard is enabled and a frame is written while responses are unread
+    #[error("attempted to write with {0} unread responses")]
+    UnreadResponses(usize),
+
+    /// Occurs when calling a method which the connection's runtime does not support, e.g. `drain_pending` on async-std
+    #[error("{0} is not supported on this runtime")]
+    Unsupported(&'static str),
+
+    /// Occurs when using a connection after a write to it failed, since part of a frame may have been sent
+    #[error("connection is closed after a failed write")]
+    Closed,
+}
+
+impl ConnectionError {
+    /// Returns whether the error may not recur on a new connection, i.e. an I/O error, a timeout, a failed lookup,
+    /// the server closing or refusing the connection, or the connection being closed by a failed write
+    pub(crate) fn is_transient(&self) -> bool {
+        matches!(
+            self,
+            ConnectionError::TCPError(_)
+                | ConnectionError::DnsFailure(_)
+                | ConnectionError::ConnectionRefused(_)
+                | ConnectionError::ConnectTimeout(_)
+                | ConnectionError::Eof
+                | ConnectionError::Timeout
+                | ConnectionError::Closed
+        )
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+/// Represents an invalid connection option
+pub enum ConfigError {
+    /// Occurs when the host is empty
+    #[error("host must not be empty")]
+    EmptyHost,
+
+    /// Occurs when the port is zero
+    #[error("port must not be zero")]
+    ZeroPort,
+
+    /// Occurs when the max buffer growth is zero
+    #[error("max buffer growth must not be zero")]
+    ZeroMaxBufferGrowth,
+
+    /// Occurs when the max frame size is zero
+    #[error("max frame size must not be zero")]
+    ZeroMaxFrameSize,
 }
 
 impl Connection {
     /// Creates a new connection from a TcpStream
     pub async fn connect(options: &ConnectionOptions) -> Result<Self, ConnectionError> {
-        let stream = TcpStream::connect(format!("{}:{}", options.host(), options.port())).await?;
-        Ok(Connection {
-            stream,
+        options.validate()?;
+        #[cfg(feature = "socks")]
+        if let Some(proxy) = options.socks5_proxy() {
+            let stream = proxy.connect(options).await?;
+            return Ok(Connection::from_stream(stream, options));
+        }
+        let stream = connect_stream(options.host(), options.port(), options).await?;
+        Ok(Connection::from_stream(stream, options))
+    }
+
+    pub(crate) fn from_stream(stream: impl Into<Stream>, options: &ConnectionOptions) -> Self {
+        Connection {
+            stream: stream.into(),
+            runtime: options.runtime(),
             buf: BytesMut::with_capacity(4096),
-        })
+            max_buffer_growth: options.max_buffer_growth(),
+            dialect: options.dialect(),
+            attributes: None,
+            pending_attributes: None,
+            last_used: Instant::now(),
+            limits: options.frame_limits(),
+            read_timeout: options.read_timeout(),
+            write_timeout: options.write_timeout(),
+            pipeline_guard: PipelineGuard::Off,
+            pending_responses: 0,
+            bytes_written: 0,
+            slow_query_threshold: options.slow_query_threshold(),
+            closed: false,
+            pending_error: None,
+        }
+    }
+
+    /// Returns the dialect used to encode commands
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
     }
 
-    /// Reads a frame from the connection and parses it
+    /// Reads a frame from the connection and parses it.
+    /// Attribute frames sent ahead of the reply are not returned, they can be retrieved with `take_attributes`
     pub async fn read_frame(&mut self) -> Result<Frame, ConnectionError> {
+        self.read_frame_head(usize::MAX).await
+    }
+
+    /// Reads a frame from the connection like `read_frame`, but if the frame is an array only its first `head`
+    /// elements are returned. The remaining elements are skipped over in the buffer without being built
+    pub async fn read_frame_head(&mut self, head: usize) -> Result<Frame, ConnectionError> {
+        self.check_read()?;
+        let frame = match self.read_timeout {
+            Some(read_timeout) => self
+                .runtime
+                .timeout(read_timeout, self.read_frame_until_complete(head))
+                .await
+                .ok_or(ConnectionError::Timeout)??,
+            None => self.read_frame_until_complete(head).await?,
+        };
+        self.track_read();
+        Ok(frame)
+    }
+
+    /// Reads a frame like `read_frame`, but returns `Ok(None)` if no complete frame arrives within the timeout,
+    /// e.g. to poll a subscription between heartbeats. This replaces the connection's read timeout for this read.
+    /// Bytes of a partial frame stay buffered, so the connection can keep being read after a timeout
+    pub async fn read_frame_or_timeout(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<Option<Frame>, ConnectionError> {
+        self.check_read()?;
+        let frame = self
+            .runtime
+            .timeout(timeout, self.read_frame_until_complete(usize::MAX))
+            .await
+            .transpose()?;
+        if frame.is_some() {
+            self.track_read();
+        }
+        Ok(frame)
+    }
+
+    /// Sets how reads are checked against writes, resetting the count of unread responses to zero.
+    /// It should be set while no responses are outstanding, e.g. right after connecting
+    pub fn set_pipeline_guard(&mut self, pipeline_guard: PipelineGuard) {
+        self.pipeline_guard = pipeline_guard;
+        self.pending_responses = 0;
+    }
+
+    /// Returns how reads are checked against writes
+    pub fn pipeline_guard(&self) -> PipelineGuard {
+        self.pipeline_guard
+    }
+
+    /// Returns the number of frames written whose response has not been read yet.
+    /// This is only tracked while the pipeline guard is enabled, otherwise it is always zero
+    pub fn pending_responses(&self) -> usize {
+        self.pending_responses
+    }
+
+    /// Returns whether a write to the connection failed. The server may have received part of a frame,
+    /// so every further read or write fails with `ConnectionError::Closed` and the connection should be dropped
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// Returns the error which closed the connection, if any, leaving `None` in its place.
+    /// Later operations only report `ConnectionError::Closed`, this lets the cause be inspected without issuing a command
+    pub fn take_error(&mut self) -> Option<ConnectionError> {
+        self.pending_error.take()
+    }
+
+    /// Closes the connection after a failed write, keeping a copy of the error for `take_error`
+    fn close(&mut self, error: &ConnectionError) {
+        self.closed = true;
+        self.pending_error = Some(match error {
+            ConnectionError::TCPError(e) => {
+                ConnectionError::TCPError(io::Error::new(e.kind(), e.to_string()))
+            }
+            ConnectionError::Timeout => ConnectionError::Timeout,
+            _ => ConnectionError::Closed,
+        });
+    }
+
+    fn check_read(&self) -> Result<(), ConnectionError> {
+        if self.closed {
+            return Err(ConnectionError::Closed);
+        }
+        if self.pipeline_guard != PipelineGuard::Off && self.pending_responses == 0 {
+            return Err(ConnectionError::UnexpectedRead);
+        }
+        Ok(())
+    }
+
+    fn track_read(&mut self) {
+        if self.pipeline_guard != PipelineGuard::Off {
+            self.pending_responses -= 1;
+        }
+    }
+
+    fn track_write(&mut self, frames: usize) -> Result<(), ConnectionError> {
+        match self.pipeline_guard {
+            PipelineGuard::Off => return Ok(()),
+            PipelineGuard::Strict if self.pending_responses > 0 => {
+                return Err(ConnectionError::UnreadResponses(self.pending_responses))
+            }
+            _ => {}
+        }
+        self.pending_responses += frames;
+        Ok(())
+    }
+
+    /// Sets the timeout for reading a frame used by subsequent reads, `None` waits indefinitely.
+    /// Bytes received before a read times out stay buffered, so a later read picks up where it stopped
+    pub fn set_read_timeout(&mut self, read_timeout: Option<Duration>) {
+        self.read_timeout = read_timeout;
+    }
+
+    /// Sets the timeout for writing a frame used by subsequent writes, `None` waits indefinitely.
+    /// A write which times out may have been partially written, so it closes the connection
+    pub fn set_write_timeout(&mut self, write_timeout: Option<Duration>) {
+        self.write_timeout = write_timeout;
+    }
+
+    /// Returns the timeout for reading a frame
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Returns the timeout for writing a frame
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Sets how long a query can take before it is logged as slow, `None` disables the slow query log
+    pub fn set_slow_query_threshold(&mut self, slow_query_threshold: Option<Duration>) {
+        self.slow_query_threshold = slow_query_threshold;
+    }
+
+    /// Returns how long a query can take before it is logged as slow
+    pub fn slow_query_threshold(&self) -> Option<Duration> {
+        self.slow_query_threshold
+    }
+
+    async fn read_frame_until_complete(&mut self, head: usize) -> Result<Frame, ConnectionError> {
+        loop {
+            match self.parse_frame(head)? {
+                Some(Frame::Attribute(attributes)) => {
+                    self.pending_attributes = Some(attributes);
+                    continue;
+                }
+                Some(frame) => {
+                    self.attributes = self.pending_attributes.take();
+                    self.last_used = Instant::now();
+                    return Ok(frame);
+                }
+                None => {}
+            }
+
+            self.read_more().await?;
+        }
+    }
+
+    /// Reads a frame from the connection and drives the visitor over it instead of building it.
+    /// The frame is only visited once it has been fully buffered, so the visitor sees each value exactly once.
+    /// Attribute frames sent ahead of the reply are not visited, they can be retrieved with `take_attributes`
+    pub async fn read_frame_visit<V: FrameVisitor + ?Sized>(
+        &mut self,
+        visitor: &mut V,
+    ) -> Result<(), CommandError> {
+        self.check_read()?;
+        let result = match self.read_timeout {
+            Some(read_timeout) => self
+                .runtime
+                .timeout(read_timeout, self.visit_frame_until_complete(visitor))
+                .await
+                .ok_or(ConnectionError::Timeout)??,
+            None => self.visit_frame_until_complete(visitor).await?,
+        };
+        self.track_read();
+        result
+    }
+
+    /// Visits the next frame once it is fully buffered. The outer error is a failure to read the frame,
+    /// while the inner result is the visitor's, which has consumed the whole frame either way
+    async fn visit_frame_until_complete<V: FrameVisitor + ?Sized>(
+        &mut self,
+        visitor: &mut V,
+    ) -> Result<Result<(), CommandError>, ConnectionError> {
         loop {
-            if let Some(frame) = self.parse_frame()? {
-                return Ok(frame);
+            if self.buf.first() == Some(&ATTRIBUTE_IDENT) {
+                if let Some(Frame::Attribute(attributes)) = self.parse_frame(usize::MAX)? {
+                    self.pending_attributes = Some(attributes);
+                    continue;
+                }
+            } else {
+                let mut cursor = Cursor::new(&self.buf[..]);
+                match frame::check_with_limits(&mut cursor, &self.limits) {
+                    Ok(()) => {
+                        let len = cursor.position() as usize;
+                        let result = frame::visit(&mut Cursor::new(&self.buf[..len]), visitor);
+                        self.buf.advance(len);
+                        self.attributes = self.pending_attributes.take();
+                        self.last_used = Instant::now();
+                        return Ok(result);
+                    }
+                    Err(ParseFrameError::Incomplete) => {}
+                    Err(e) => return Err(e.into()),
+                }
             }
 
-            if self.stream.read_buf(&mut self.buf).await? == 0 {
-                return Err(ConnectionError::Eof);
+            self.read_more().await?;
+        }
+    }
+
+    /// Reads more data into the buffer, failing if an incomplete frame already exceeds the maximum frame size
+    async fn read_more(&mut self) -> Result<(), ConnectionError> {
+        if self.buf.len() > self.limits.max_frame_size() {
+            return Err(ParseFrameError::FrameTooLarge.into());
+        }
+
+        self.reserve_read_capacity();
+        if read_retrying(&mut self.stream, &mut self.buf).await? == 0 {
+            return Err(ConnectionError::Eof);
+        }
+        Ok(())
+    }
+
+    /// Returns when a frame was last read from or written to the connection, or when it was created if it has not been used.
+    /// This lets idle connections be found and closed
+    pub fn last_used(&self) -> Instant {
+        self.last_used
+    }
+
+    /// Returns the attributes which were sent ahead of the last frame read, as alternating keys and values
+    pub fn take_attributes(&mut self) -> Option<Vec<Frame>> {
+        self.attributes.take()
+    }
+
+    /// Parses and removes every frame which has fully arrived without waiting for more data, e.g. pushes which
+    /// accumulated since the last reply. A trailing partial frame is left buffered, so the next `read_frame`
+    /// starts at a frame boundary. Attribute frames are returned as is.
+    /// Frames are not told apart from replies, so it must only be called while no command awaits its reply.
+    /// With the pipeline guard enabled this is checked and unread responses fail with `UnreadResponses`.
+    /// Reading stops once more than the max frame size is buffered, the rest is left for the next call.
+    /// It is only supported on tokio, other runtimes fail with `ConnectionError::Unsupported`
+    pub fn drain_pending(&mut self) -> Result<Vec<Frame>, ConnectionError> {
+        if self.closed {
+            return Err(ConnectionError::Closed);
+        }
+        if !self.stream.supports_readiness() {
+            return Err(ConnectionError::Unsupported("drain_pending"));
+        }
+        if self.pipeline_guard != PipelineGuard::Off && self.pending_responses > 0 {
+            return Err(ConnectionError::UnreadResponses(self.pending_responses));
+        }
+        while self.buf.len() <= self.limits.max_frame_size() {
+            self.reserve_read_capacity();
+            match self.stream.try_read_buf(&mut self.buf) {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.into()),
             }
         }
+
+        let mut frames = Vec::new();
+        while let Some(frame) = self.parse_frame(usize::MAX)? {
+            frames.push(frame);
+        }
+        Ok(frames)
     }
 
-    fn parse_frame(&mut self) -> Result<Option<Frame>, ConnectionError> {
+    /// Builds a command from the args, executes it and decodes the result
+    pub async fn command<T: FromSegmentFrame, A: ToSegmentFrame>(
+        &mut self,
+        args: &[A],
+    ) -> Result<T, CommandError> {
+        Command::from_args(args).query(self).await
+    }
+
+    /// Sends ECHO with the payload and returns the bytes the server echoed back. The payload is sent as a string frame,
+    /// so this checks that arbitrary binary data, e.g. holding CRLF or NUL, round trips through the connection
+    pub async fn echo(&mut self, payload: &[u8]) -> Result<Bytes, CommandError> {
+        let mut cmd = Command::new();
+        cmd.arg("ECHO").arg(Bytes::copy_from_slice(payload));
+        cmd.query(self).await
+    }
+
+    /// Sends RESET to return the connection to its defaults on the server, e.g. leaving subscribe or monitor mode,
+    /// and waits for its `RESET` reply, or the `OK` older servers send. Unread responses tracked by the pipeline guard are
+    /// discarded whatever they hold. Any other reply ahead of the reset's own is reported as the reset failing, an error
+    /// reply as a query error and anything else as an incompatible type
+    pub async fn server_reset(&mut self) -> Result<(), CommandError> {
+        let pipeline_guard = self.pipeline_guard;
+        let pending_responses = self.pending_responses;
+        self.set_pipeline_guard(PipelineGuard::Off);
+        let result = self.send_reset(pending_responses).await;
+        self.set_pipeline_guard(pipeline_guard);
+        result
+    }
+
+    async fn send_reset(&mut self, pending_responses: usize) -> Result<(), CommandError> {
+        let cmd = Command::from_args(&["RESET"]).into_frame();
+        self.write_frame(&command::encode(&cmd, self.dialect))
+            .await?;
+        for _ in 0..pending_responses {
+            self.read_frame().await?;
+        }
+        match self.read_frame().await? {
+            Frame::String(reply) if reply == "RESET" || reply == "OK" => Ok(()),
+            Frame::Error(val) => Err(CommandError::QueryError(
+                String::from_utf8_lossy(&val).into_owned(),
+            )),
+            other => Err(CommandError::IncompatibleType(other.as_str(), "RESET")),
+        }
+    }
+
+    /// Sets the value of a key which expires after the given ttl
+    pub async fn set_ex<T: FromSegmentFrame, V: ToSegmentFrame>(
+        &mut self,
+        key: &str,
+        value: V,
+        ttl: Duration,
+    ) -> Result<T, CommandError> {
+        Command::set_ex(key, value, ttl).query(self).await
+    }
+
+    /// Writes all the commands, flushes once and then reads a response for each command.
+    /// Error frames returned by the server are reported per command without aborting the pipeline,
+    /// while connection errors abort it since the remaining responses can not be read reliably
+    pub async fn query_pipeline(
+        &mut self,
+        commands: Vec<Command>,
+    ) -> Result<Vec<Result<Frame, CommandError>>, ConnectionError> {
+        let len = commands.len();
+        for cmd in commands {
+            let cmd = cmd.into_frame();
+            self.queue_frame(&command::encode(&cmd, self.dialect))
+                .await?;
+        }
+        self.flush().await?;
+
+        let mut results = Vec::with_capacity(len);
+        for _ in 0..len {
+            results.push(match self.read_frame().await? {
+                Frame::Error(val) => Err(CommandError::QueryError(
+                    String::from_utf8_lossy(&val).into_owned(),
+                )),
+                frame => Ok(frame),
+            });
+        }
+
+        Ok(results)
+    }
+
+    fn reserve_read_capacity(&mut self) {
+        if let Some(growth) = self.max_buffer_growth {
+            if self.buf.len() == self.buf.capacity() {
+                // BytesMut::reserve would double the capacity. Growing the underlying vector exactly keeps it at
+                // len + growth, drops the parsed prefix and lets the allocator extend the allocation in place
+                let mut buf = Vec::from(mem::take(&mut self.buf));
+                buf.reserve_exact(growth);
+                self.buf = BytesMut::from(Bytes::from(buf));
+            }
+        }
+    }
+
+    fn parse_frame(&mut self, head: usize) -> Result<Option<Frame>, ConnectionError> {
         let mut cursor = Cursor::new(&self.buf[..]);
-        match frame::parse(&mut cursor) {
+        match frame::parse_head_with_limits(&mut cursor, &self.limits, head) {
             Ok(frame) => {
                 self.buf.advance(cursor.position() as usize);
                 Ok(Some(frame))
@@ -73,86 +601,215 @@ impl Connection {
         }
     }
 
-    /// Writes a frame to the connection
+    /// Writes a frame to the connection and flushes it
     pub async fn write_frame(&mut self, frame: &Frame) -> Result<(), ConnectionError> {
-        match frame {
-            Frame::Array(array) => {
-                self.stream.write_u8(ARRAY_IDENT).await?;
-                self.stream
-                    .write_all(format!("{}\r\n", array.len()).as_bytes())
-                    .await?;
-                for value in array {
-                    self.write_value(value).await?;
-                }
-            }
-            Frame::Map(map) => {
-                self.stream.write_u8(MAP_IDENT).await?;
-                self.stream
-                    .write_all(format!("{}\r\n", map.len() / 2).as_bytes())
-                    .await?;
-                for value in map {
-                    self.write_value(value).await?;
-                }
+        self.queue_frame(frame).await?;
+        self.flush().await
+    }
+
+    /// Writes a frame to the connection and flushes it like `write_frame`, returning the number of bytes written.
+    /// This is the frame's `serialized_len`, the frame is always written whole
+    pub async fn write_frame_counted(&mut self, frame: &Frame) -> Result<usize, ConnectionError> {
+        let buf = self.serialize_frame(frame)?;
+        self.queue_raw(&buf).await?;
+        self.flush().await?;
+        Ok(buf.len())
+    }
+
+    /// Returns the total number of bytes written to the connection
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Writes a frame to the connection without flushing it. The connection does not buffer writes in process,
+    /// so this returns once the frame has been handed to the underlying stream, i.e. the kernel socket buffer.
+    /// Use `flush` to wait for the stream to be flushed after queueing a batch of frames
+    pub async fn queue_frame(&mut self, frame: &Frame) -> Result<(), ConnectionError> {
+        let buf = self.serialize_frame(frame)?;
+        self.queue_raw(&buf).await
+    }
+
+    fn serialize_frame(&mut self, frame: &Frame) -> Result<BytesMut, ConnectionError> {
+        if frame::has_unpaired_entries(frame) {
+            return Err(ConnectionError::UnpairedMapEntry);
+        }
+        self.track_write(1)?;
+        let mut buf = BytesMut::with_capacity(frame.serialized_len());
+        frame::serialize(frame, &mut buf);
+        Ok(buf)
+    }
+
+    /// Writes already serialized frames to the connection and flushes them, e.g. a `PreparedCommand`.
+    /// The bytes are written as is, they must hold complete frames or the server will be desynced
+    pub async fn write_raw(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+        if self.pipeline_guard != PipelineGuard::Off {
+            let mut cursor = Cursor::new(data);
+            let mut frames = 0;
+            while frame::check_with_limits(&mut cursor, &self.limits).is_ok() {
+                frames += 1;
             }
-            _ => self.write_value(frame).await?,
+            self.track_write(frames)?;
         }
+        self.queue_raw(data).await?;
+        self.flush().await
+    }
 
-        self.stream.flush().await?;
+    async fn queue_raw(&mut self, data: &[u8]) -> Result<(), ConnectionError> {
+        if self.closed {
+            return Err(ConnectionError::Closed);
+        }
+        let result = match self.write_timeout {
+            Some(write_timeout) => self
+                .runtime
+                .timeout(write_timeout, write_all_retrying(&mut self.stream, data))
+                .await
+                .ok_or(ConnectionError::Timeout)
+                .and_then(|result| Ok(result?)),
+            None => Ok(write_all_retrying(&mut self.stream, data).await?),
+        };
+        if let Err(e) = &result {
+            self.close(e);
+        }
+        result?;
+        self.bytes_written += data.len() as u64;
+        self.last_used = Instant::now();
         Ok(())
     }
 
-    async fn write_value(&mut self, frame: &Frame) -> Result<(), ConnectionError> {
-        match frame {
-            Frame::String(data) => {
-                let len = data.len();
-                self.stream.write_u8(STRING_IDENT).await?;
-                self.stream
-                    .write_all(format!("{}\r\n", len).as_bytes())
-                    .await?;
-                self.stream.write_all(data).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            Frame::Integer(data) => {
-                self.stream.write_u8(INTEGER_IDENT).await?;
-                self.stream
-                    .write_all(format!("{}\r\n", data).as_bytes())
-                    .await?;
-            }
-            Frame::Boolean(data) => {
-                self.stream.write_u8(BOOLEAN_IDENT).await?;
-                if *data {
-                    self.stream
-                        .write_all(format!("{}\r\n", 1).as_bytes())
-                        .await?;
-                } else {
-                    self.stream
-                        .write_all(format!("{}\r\n", 0).as_bytes())
-                        .await?;
+    /// Consumes the connection and returns the bytes which were buffered but not yet parsed along with the underlying stream.
+    /// This is used when a command switches the connection to a raw byte stream after a reply, the buffered bytes
+    /// must be consumed before reading from the stream
+    pub fn take_raw(self) -> (Bytes, Stream) {
+        (self.buf.freeze(), self.stream)
+    }
+
+    /// Sets the size of the socket's receive buffer, the operating system may round or clamp it
+    pub fn set_recv_buffer_size(&self, size: usize) -> Result<(), ConnectionError> {
+        self.stream.socket().set_recv_buffer_size(size)?;
+        Ok(())
+    }
+
+    /// Returns the size of the socket's receive buffer as reported by the operating system
+    pub fn recv_buffer_size(&self) -> Result<usize, ConnectionError> {
+        Ok(self.stream.socket().recv_buffer_size()?)
+    }
+
+    /// Sets the size of the socket's send buffer, the operating system may round or clamp it
+    pub fn set_send_buffer_size(&self, size: usize) -> Result<(), ConnectionError> {
+        self.stream.socket().set_send_buffer_size(size)?;
+        Ok(())
+    }
+
+    /// Returns the size of the socket's send buffer as reported by the operating system
+    pub fn send_buffer_size(&self) -> Result<usize, ConnectionError> {
+        Ok(self.stream.socket().send_buffer_size()?)
+    }
+
+    /// Waits until the underlying stream is readable. This is meant for advanced use such as
+    /// waiting on several connections with `tokio::select!`, it does not read or parse any data.
+    /// It is only supported on tokio, other runtimes fail with `ConnectionError::Unsupported`
+    pub async fn readable(&self) -> Result<(), ConnectionError> {
+        if !self.stream.supports_readiness() {
+            return Err(ConnectionError::Unsupported("readable"));
+        }
+        self.stream.readable().await?;
+        Ok(())
+    }
+
+    /// Waits until the underlying stream is writable. This is meant for advanced use such as
+    /// waiting on several connections with `tokio::select!`, it does not write any data.
+    /// It is only supported on tokio, other runtimes fail with `ConnectionError::Unsupported`
+    pub async fn writable(&self) -> Result<(), ConnectionError> {
+        if !self.stream.supports_readiness() {
+            return Err(ConnectionError::Unsupported("writable"));
+        }
+        self.stream.writable().await?;
+        Ok(())
+    }
+
+    /// Flushes the frames written to the connection
+    pub async fn flush(&mut self) -> Result<(), ConnectionError> {
+        if self.closed {
+            return Err(ConnectionError::Closed);
+        }
+        loop {
+            match self.stream.flush().await {
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    let e = e.into();
+                    self.close(&e);
+                    return Err(e);
                 }
+                Ok(()) => return Ok(()),
             }
-            Frame::Null => {
-                self.stream.write_all(b"-\r\n").await?;
-            }
-            Frame::Double(data) => {
-                self.stream.write_u8(DOUBLE_IDENT).await?;
-                self.stream
-                    .write_all(format!("{}\r\n", data).as_bytes())
-                    .await?;
-            }
-            Frame::Error(data) => {
-                let len = data.len();
-                self.stream.write_u8(ERROR_IDENT).await?;
-                self.stream
-                    .write_all(format!("{}\r\n", len).as_bytes())
-                    .await?;
-                self.stream.write_all(data).await?;
-                self.stream.write_all(b"\r\n").await?;
-            }
-            _ => unreachable!(),
         }
+    }
+}
 
-        Ok(())
+/// Resolves the host with the options' resolver and opens a socket to the first address accepting the connection
+pub(crate) async fn connect_stream(
+    host: &str,
+    port: u16,
+    options: &ConnectionOptions,
+) -> Result<Stream, ConnectionError> {
+    let runtime = options.runtime();
+    let addrs = match options.resolver() {
+        Some(resolver) => resolver.resolve(host, port).await,
+        None => runtime.lookup_host(host, port).await,
+    }
+    .map_err(ConnectionError::DnsFailure)?;
+    if addrs.is_empty() {
+        return Err(ConnectionError::DnsFailure(io::Error::new(
+            io::ErrorKind::NotFound,
+            "host did not resolve to any address",
+        )));
     }
+    runtime
+        .connect(&addrs, options.bind_addr())
+        .await
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::ConnectionRefused => ConnectionError::ConnectionRefused(e),
+            io::ErrorKind::TimedOut => ConnectionError::ConnectTimeout(e),
+            _ => ConnectionError::TCPError(e),
+        })
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> Self {
+        Self::new(DEFAULT_HOST, DEFAULT_PORT)
+    }
+}
+
+/// Reads into the buffer, retrying reads which were interrupted by a signal.
+/// Tokio streams wait for readiness instead of returning `WouldBlock`, so it only shows up in `drain_pending`
+/// which uses non-blocking reads and treats it as nothing being available
+async fn read_retrying<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut BytesMut,
+) -> io::Result<usize> {
+    loop {
+        match reader.read_buf(buf).await {
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+/// Writes all the data, retrying writes which were interrupted by a signal. Unlike `write_all`,
+/// an interrupted write is not reported as an error after part of the data may have been written
+async fn write_all_retrying<W: AsyncWrite + Unpin>(
+    writer: &mut W,
+    mut data: &[u8],
+) -> io::Result<()> {
+    while !data.is_empty() {
+        match writer.write(data).await {
+            Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+            Ok(n) => data = &data[n..],
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
 }
 
 impl ConnectionOptions {
@@ -161,9 +818,124 @@ impl ConnectionOptions {
         ConnectionOptions {
             host: host.to_string(),
             port,
+            max_buffer_growth: None,
+            resolver: None,
+            #[cfg(feature = "socks")]
+            socks5_proxy: None,
+            dialect: Dialect::default(),
+            bind_addr: None,
+            max_frame_size: None,
+            max_elements: None,
+            read_timeout: None,
+            write_timeout: None,
+            slow_query_threshold: None,
+            runtime: Runtime::default(),
         }
     }
 
+    /// Sets the maximum number of bytes a single frame read from the server can take, frames are unbounded by default
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) -> &mut Self {
+        self.max_frame_size = Some(max_frame_size);
+        self
+    }
+
+    /// Sets the maximum number of elements a single frame read from the server can contain across all its nested collections
+    pub fn set_max_elements(&mut self, max_elements: usize) -> &mut Self {
+        self.max_elements = Some(max_elements);
+        self
+    }
+
+    /// Sets the timeout for reading a frame, reads wait indefinitely by default
+    pub fn set_read_timeout(&mut self, read_timeout: Duration) -> &mut Self {
+        self.read_timeout = Some(read_timeout);
+        self
+    }
+
+    /// Sets the timeout for writing a frame, writes wait indefinitely by default
+    pub fn set_write_timeout(&mut self, write_timeout: Duration) -> &mut Self {
+        self.write_timeout = Some(write_timeout);
+        self
+    }
+
+    /// Sets how long a query can take before a warning with the command name and the elapsed time is logged.
+    /// Slow queries are logged through `tracing` when the tracing feature is enabled, they are not logged by default
+    pub fn set_slow_query_threshold(&mut self, slow_query_threshold: Duration) -> &mut Self {
+        self.slow_query_threshold = Some(slow_query_threshold);
+        self
+    }
+
+    /// Sets the runtime the connection is driven by, tokio is used by default
+    pub fn set_runtime(&mut self, runtime: Runtime) -> &mut Self {
+        self.runtime = runtime;
+        self
+    }
+
+    /// Sets the local address the connection is bound to before connecting
+    pub fn set_bind_addr(&mut self, bind_addr: SocketAddr) -> &mut Self {
+        self.bind_addr = Some(bind_addr);
+        self
+    }
+
+    /// Sets the dialect used to encode commands
+    pub fn set_dialect(&mut self, dialect: Dialect) -> &mut Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets the resolver used to look up the host, the system resolver is used by default
+    pub fn set_resolver<R: Resolver + 'static>(&mut self, resolver: R) -> &mut Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    /// Sets the SOCKS5 proxy connections are routed through
+    #[cfg(feature = "socks")]
+    pub fn set_socks5_proxy(&mut self, proxy: Socks5Proxy) -> &mut Self {
+        self.socks5_proxy = Some(proxy);
+        self
+    }
+
+    /// Sets the connection host
+    pub fn set_host(&mut self, host: &str) -> &mut Self {
+        self.host = host.to_string();
+        self
+    }
+
+    /// Sets the connection port
+    pub fn set_port(&mut self, port: u16) -> &mut Self {
+        self.port = port;
+        self
+    }
+
+    /// Sets the maximum number of bytes the read buffer grows by once it is full, so its capacity stays within
+    /// the buffered bytes plus the growth. By default the buffer doubles its capacity, setting this trades a few more
+    /// reads and reallocations for tighter memory use
+    pub fn set_max_buffer_growth(&mut self, growth: usize) -> &mut Self {
+        self.max_buffer_growth = Some(growth);
+        self
+    }
+
+    /// Checks that the options are valid
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.host.is_empty() {
+            return Err(ConfigError::EmptyHost);
+        }
+
+        if self.port == 0 {
+            return Err(ConfigError::ZeroPort);
+        }
+
+        if self.max_buffer_growth == Some(0) {
+            return Err(ConfigError::ZeroMaxBufferGrowth);
+        }
+
+        if self.max_frame_size == Some(0) {
+            return Err(ConfigError::ZeroMaxFrameSize);
+        }
+
+        Ok(())
+    }
+
     /// Returns the connection host
     pub fn host(&self) -> &str {
         &self.host
@@ -173,4 +945,985 @@ impl ConnectionOptions {
     pub fn port(&self) -> u16 {
         self.port
     }
+
+    /// Returns the maximum number of bytes the read buffer grows by
+    pub fn max_buffer_growth(&self) -> Option<usize> {
+        self.max_buffer_growth
+    }
+
+    /// Returns the dialect used to encode commands
+    pub fn dialect(&self) -> Dialect {
+        self.dialect
+    }
+
+    /// Returns the maximum number of bytes a single frame read from the server can take
+    pub fn max_frame_size(&self) -> Option<usize> {
+        self.max_frame_size
+    }
+
+    /// Returns the maximum number of elements a single frame read from the server can contain
+    pub fn max_elements(&self) -> Option<usize> {
+        self.max_elements
+    }
+
+    /// Returns the timeout for reading a frame
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout
+    }
+
+    /// Returns the timeout for writing a frame
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout
+    }
+
+    /// Returns how long a query can take before it is logged as slow
+    pub fn slow_query_threshold(&self) -> Option<Duration> {
+        self.slow_query_threshold
+    }
+
+    /// Returns the runtime the connection is driven by
+    pub fn runtime(&self) -> Runtime {
+        self.runtime
+    }
+
+    /// Returns the limits applied while parsing frames read from the server
+    pub fn frame_limits(&self) -> FrameLimits {
+        let mut limits = FrameLimits::new();
+        if let Some(max_frame_size) = self.max_frame_size {
+            limits.set_max_frame_size(max_frame_size);
+        }
+        if let Some(max_elements) = self.max_elements {
+            limits.set_max_elements(max_elements);
+        }
+        limits
+    }
+
+    /// Returns the local address the connection is bound to
+    pub fn bind_addr(&self) -> Option<SocketAddr> {
+        self.bind_addr
+    }
+
+    /// Returns the custom resolver if one is set
+    pub fn resolver(&self) -> Option<&dyn Resolver> {
+        self.resolver.as_deref()
+    }
+
+    /// Returns the SOCKS5 proxy connections are routed through
+    #[cfg(feature = "socks")]
+    pub fn socks5_proxy(&self) -> Option<&Socks5Proxy> {
+        self.socks5_proxy.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolver::ResolveFuture;
+    use crate::test_server::TestServer;
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+    use tokio::io::ReadBuf;
+    use tokio::net::{TcpListener, TcpStream};
+
+    #[derive(Debug)]
+    struct FixedResolver(Vec<SocketAddr>);
+
+    impl Resolver for FixedResolver {
+        fn resolve<'a>(&'a self, _host: &'a str, _port: u16) -> ResolveFuture<'a> {
+            Box::pin(async move { Ok(self.0.clone()) })
+        }
+    }
+
+    async fn connection_pair() -> (Connection, TcpStream) {
+        connection_pair_with(|_| {}).await
+    }
+
+    async fn connection_pair_with(
+        configure: impl FnOnce(&mut ConnectionOptions),
+    ) -> (Connection, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut options = ConnectionOptions::new("127.0.0.1", port);
+        configure(&mut options);
+        let (connection, accepted) = tokio::join!(Connection::connect(&options), listener.accept());
+        (connection.unwrap(), accepted.unwrap().0)
+    }
+
+    #[test]
+    fn default_points_at_local_server() {
+        let options = ConnectionOptions::default();
+        assert_eq!(options.host(), "127.0.0.1");
+        assert_eq!(options.port(), 1698);
+        assert_eq!(options.max_buffer_growth(), None);
+        assert_eq!(options.max_frame_size(), None);
+        assert_eq!(options.validate(), Ok(()))
+    }
+
+    #[test]
+    fn validate_given_valid_options_returns_ok() {
+        let mut options = ConnectionOptions::new("127.0.0.1", 1698);
+        options.set_max_buffer_growth(4096);
+        assert_eq!(options.validate(), Ok(()))
+    }
+
+    #[test]
+    fn validate_given_empty_host_returns_empty_host_error() {
+        let options = ConnectionOptions::new("", 1698);
+        assert_eq!(options.validate(), Err(ConfigError::EmptyHost))
+    }
+
+    #[test]
+    fn validate_given_zero_port_returns_zero_port_error() {
+        let options = ConnectionOptions::new("127.0.0.1", 0);
+        assert_eq!(options.validate(), Err(ConfigError::ZeroPort))
+    }
+
+    #[test]
+    fn validate_given_zero_max_buffer_growth_returns_zero_max_buffer_growth_error() {
+        let mut options = ConnectionOptions::new("127.0.0.1", 1698);
+        options.set_max_buffer_growth(0);
+        assert_eq!(options.validate(), Err(ConfigError::ZeroMaxBufferGrowth))
+    }
+
+    #[test]
+    fn validate_given_zero_max_frame_size_returns_zero_max_frame_size_error() {
+        let mut options = ConnectionOptions::new("127.0.0.1", 1698);
+        options.set_max_frame_size(0);
+        assert_eq!(options.validate(), Err(ConfigError::ZeroMaxFrameSize))
+    }
+
+    #[tokio::test]
+    async fn connect_given_invalid_options_returns_config_error() {
+        let options = ConnectionOptions::new("", 1698);
+        assert!(matches!(
+            Connection::connect(&options).await,
+            Err(ConnectionError::ConfigError(ConfigError::EmptyHost))
+        ))
+    }
+
+    #[tokio::test]
+    async fn connect_given_custom_resolver_connects_to_resolved_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut options = ConnectionOptions::new("segment.invalid", 1698);
+        options.set_resolver(FixedResolver(vec![addr]));
+
+        let (connection, accepted) = tokio::join!(Connection::connect(&options), listener.accept());
+        let connection = connection.unwrap();
+        let (server, _) = accepted.unwrap();
+        assert_eq!(connection.stream.peer_addr().unwrap(), addr);
+        assert_eq!(
+            server.peer_addr().unwrap(),
+            connection.stream.local_addr().unwrap()
+        )
+    }
+
+    #[derive(Debug)]
+    struct FailingResolver;
+
+    impl Resolver for FailingResolver {
+        fn resolve<'a>(&'a self, _host: &'a str, _port: u16) -> ResolveFuture<'a> {
+            Box::pin(async { Err(io::Error::other("no such host")) })
+        }
+    }
+
+    #[tokio::test]
+    async fn connect_given_unresolvable_host_returns_dns_failure() {
+        let mut options = ConnectionOptions::new("segment.invalid", 1698);
+        options.set_resolver(FailingResolver);
+        assert!(matches!(
+            Connection::connect(&options).await,
+            Err(ConnectionError::DnsFailure(_))
+        ))
+    }
+
+    #[tokio::test]
+    async fn connect_given_closed_port_returns_connection_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+
+        assert!(matches!(
+            Connection::connect(&ConnectionOptions::new("127.0.0.1", port)).await,
+            Err(ConnectionError::ConnectionRefused(_))
+        ))
+    }
+
+    #[tokio::test]
+    async fn connect_given_bind_addr_binds_local_address() {
+        let bind_addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let (connection, _server) = connection_pair_with(|options| {
+            options.set_bind_addr(bind_addr);
+        })
+        .await;
+        assert_eq!(connection.stream.local_addr().unwrap(), bind_addr)
+    }
+
+    #[tokio::test]
+    async fn connect_given_ipv4_bind_addr_skips_ipv6_addresses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let mut options = ConnectionOptions::new("segment.invalid", port);
+        options
+            .set_resolver(FixedResolver(vec![
+                SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, 1], port)),
+                SocketAddr::from(([127, 0, 0, 1], port)),
+            ]))
+            .set_bind_addr(SocketAddr::from(([127, 0, 0, 1], 0)));
+
+        let (connection, accepted) = tokio::join!(Connection::connect(&options), listener.accept());
+        accepted.unwrap();
+        assert_eq!(
+            connection.unwrap().stream.peer_addr().unwrap(),
+            listener.local_addr().unwrap()
+        )
+    }
+
+    /// A stream which fails its first read and write with `Interrupted`, then reads from `input` and writes to `output`
+    struct InterruptedOnce {
+        input: &'static [u8],
+        output: Vec<u8>,
+        read_interrupted: bool,
+        write_interrupted: bool,
+    }
+
+    impl InterruptedOnce {
+        fn new(input: &'static [u8]) -> Self {
+            InterruptedOnce {
+                input,
+                output: Vec::new(),
+                read_interrupted: false,
+                write_interrupted: false,
+            }
+        }
+    }
+
+    impl AsyncRead for InterruptedOnce {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            buf: &mut ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            if !self.read_interrupted {
+                self.read_interrupted = true;
+                return Poll::Ready(Err(io::ErrorKind::Interrupted.into()));
+            }
+            let n = self.input.len().min(buf.remaining());
+            buf.put_slice(&self.input[..n]);
+            self.input = &self.input[n..];
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    impl AsyncWrite for InterruptedOnce {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            _cx: &mut Context<'_>,
+            data: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            if !self.write_interrupted {
+                self.write_interrupted = true;
+                return Poll::Ready(Err(io::ErrorKind::Interrupted.into()));
+            }
+            // Accepts a few bytes at a time so the write is split around the interruption
+            let n = data.len().min(3);
+            self.output.extend_from_slice(&data[..n]);
+            Poll::Ready(Ok(n))
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn read_retrying_given_interrupted_read_retries() {
+        let mut stream = InterruptedOnce::new(b"%1\r\n");
+        let mut buf = BytesMut::new();
+        assert_eq!(read_retrying(&mut stream, &mut buf).await.unwrap(), 4);
+        assert_eq!(&buf[..], b"%1\r\n")
+    }
+
+    #[tokio::test]
+    async fn write_all_retrying_given_interrupted_write_retries() {
+        let mut stream = InterruptedOnce::new(b"");
+        write_all_retrying(&mut stream, b"$3\r\nfoo\r\n")
+            .await
+            .unwrap();
+        assert_eq!(&stream.output[..], b"$3\r\nfoo\r\n")
+    }
+
+    async fn assert_top_level_write(frame: Frame, expected: &[u8]) {
+        let (mut connection, mut server) = connection_pair().await;
+        connection.write_frame(&frame).await.unwrap();
+        drop(connection);
+
+        let mut received = Vec::new();
+        server.read_to_end(&mut received).await.unwrap();
+        assert_eq!(received, expected)
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_top_level_null_writes_null() {
+        assert_top_level_write(Frame::Null, b"-\r\n").await
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_top_level_integer_writes_integer() {
+        assert_top_level_write(Frame::Integer(-42), b"%-42\r\n").await
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_top_level_double_writes_double() {
+        assert_top_level_write(Frame::Double(1.5), b".1.5\r\n").await
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_top_level_boolean_writes_boolean() {
+        assert_top_level_write(Frame::Boolean(true), b"^1\r\n").await;
+        assert_top_level_write(Frame::Boolean(false), b"^0\r\n").await
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_top_level_string_writes_string() {
+        assert_top_level_write(Frame::String(Bytes::from("foo\r\n")), b"$5\r\nfoo\r\n\r\n").await;
+        assert_top_level_write(Frame::String(Bytes::new()), b"$0\r\n\r\n").await
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_top_level_error_writes_error() {
+        assert_top_level_write(Frame::Error(Bytes::from("ERR foo")), b"!7\r\nERR foo\r\n").await
+    }
+
+    #[tokio::test]
+    async fn queue_frame_then_flush_writes_frames_in_order() {
+        let (mut connection, mut server) = connection_pair().await;
+        connection
+            .queue_frame(&Frame::String(Bytes::from("foo")))
+            .await
+            .unwrap();
+        connection.queue_frame(&Frame::Integer(1)).await.unwrap();
+        connection.queue_frame(&Frame::Null).await.unwrap();
+        connection.flush().await.unwrap();
+
+        let expected = b"$3\r\nfoo\r\n%1\r\n-\r\n";
+        let mut received = vec![0; expected.len()];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received[..], &expected[..])
+    }
+
+    #[tokio::test]
+    async fn read_frame_given_max_buffer_growth_grows_buffer_in_bounded_steps() {
+        let (mut connection, mut server) = connection_pair_with(|options| {
+            options.set_max_buffer_growth(4096);
+        })
+        .await;
+        server.write_all(b"$100000\r\n").await.unwrap();
+        server.write_all(&[b'a'; 50_000]).await.unwrap();
+
+        while connection.buf.len() < 50_009 {
+            assert_eq!(
+                connection
+                    .read_frame_or_timeout(Duration::from_millis(10))
+                    .await
+                    .unwrap(),
+                None
+            );
+            assert!(connection.buf.capacity() <= connection.buf.len() + 4096);
+        }
+        server.write_all(&[b'a'; 50_000]).await.unwrap();
+        server.write_all(b"\r\n").await.unwrap();
+        let frame = connection.read_frame().await.unwrap();
+        assert_eq!(frame, Frame::String(Bytes::from(vec![b'a'; 100_000])));
+        assert!(connection.buf.capacity() <= 4096)
+    }
+
+    #[tokio::test]
+    async fn read_frame_given_large_string_in_chunks_assembles_string() {
+        let (mut connection, mut server) = connection_pair().await;
+        let data: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+        let expected = Bytes::from(data.clone());
+        let writer = tokio::spawn(async move {
+            server
+                .write_all(format!("${}\r\n", data.len()).as_bytes())
+                .await
+                .unwrap();
+            for chunk in data.chunks(4096) {
+                server.write_all(chunk).await.unwrap();
+                server.flush().await.unwrap();
+            }
+            server.write_all(b"\r\n").await.unwrap();
+            server
+        });
+
+        let frame = connection.read_frame().await.unwrap();
+        writer.await.unwrap();
+        assert_eq!(frame, Frame::String(expected))
+    }
+
+    #[tokio::test]
+    async fn readable_given_pending_data_returns_and_frame_can_be_read() {
+        let (mut connection, mut server) = connection_pair().await;
+        server.write_all(b"%1\r\n").await.unwrap();
+
+        connection.readable().await.unwrap();
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1))
+    }
+
+    #[tokio::test]
+    async fn writable_given_new_connection_returns() {
+        let (connection, _server) = connection_pair().await;
+        connection.writable().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn take_raw_after_header_frame_returns_trailing_bytes() {
+        let (mut connection, mut server) = connection_pair().await;
+        server.write_all(b"$2\r\nOK\r\nraw\r\nbytes").await.unwrap();
+        server.shutdown().await.unwrap();
+
+        let header = connection.read_frame().await.unwrap();
+        let (buffered, mut stream) = connection.take_raw();
+        let mut raw = buffered.to_vec();
+        stream.read_to_end(&mut raw).await.unwrap();
+        assert_eq!(header, Frame::String(Bytes::from("OK")));
+        assert_eq!(raw, b"raw\r\nbytes")
+    }
+
+    async fn received_command(dialect: Dialect) -> Frame {
+        let (mut connection, server) = connection_pair_with(|options| {
+            options.set_dialect(dialect);
+        })
+        .await;
+        let options = ConnectionOptions::new("127.0.0.1", 1698);
+        let server = tokio::spawn(async move {
+            let mut server = Connection::from_stream(server, &options);
+            let cmd = server.read_frame().await.unwrap();
+            server.write_frame(&Frame::Integer(1)).await.unwrap();
+            cmd
+        });
+
+        let mut cmd = Command::new();
+        cmd.arg("set").arg("foo").arg(10).arg(true);
+        cmd.query::<i64>(&mut connection).await.unwrap();
+        server.await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn query_given_typed_dialect_sends_typed_args() {
+        assert_eq!(
+            received_command(Dialect::Typed).await,
+            Frame::Array(vec![
+                Frame::String(Bytes::from("set")),
+                Frame::String(Bytes::from("foo")),
+                Frame::Integer(10),
+                Frame::Boolean(true),
+            ])
+        )
+    }
+
+    #[tokio::test]
+    async fn query_given_bulk_strings_dialect_sends_string_args() {
+        assert_eq!(
+            received_command(Dialect::BulkStrings).await,
+            Frame::Array(vec![
+                Frame::String(Bytes::from("SET")),
+                Frame::String(Bytes::from("foo")),
+                Frame::String(Bytes::from("10")),
+                Frame::String(Bytes::from("1")),
+            ])
+        )
+    }
+
+    #[tokio::test]
+    async fn query_pipeline_given_mixed_responses_returns_result_per_command() {
+        let server = TestServer::start(|frame| match frame {
+            Frame::Array(args) if args[0] == Frame::String(Bytes::from("GET")) => {
+                Frame::String(Bytes::from("bar"))
+            }
+            _ => Frame::Error(Bytes::from("ERR unknown command")),
+        })
+        .await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        let mut get = Command::new();
+        get.arg("GET").arg("foo");
+        let mut unknown = Command::new();
+        unknown.arg("FOO");
+        let mut get_again = Command::new();
+        get_again.arg("GET").arg("foo");
+
+        let results = connection
+            .query_pipeline(vec![get, unknown, get_again])
+            .await
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert_eq!(
+            results[0].as_ref().unwrap(),
+            &Frame::String(Bytes::from("bar"))
+        );
+        assert!(
+            matches!(&results[1], Err(CommandError::QueryError(msg)) if msg == "ERR unknown command")
+        );
+        assert_eq!(
+            results[2].as_ref().unwrap(),
+            &Frame::String(Bytes::from("bar"))
+        )
+    }
+
+    #[tokio::test]
+    async fn set_read_timeout_applies_to_subsequent_reads() {
+        let (mut connection, mut server) = connection_pair_with(|options| {
+            options.set_read_timeout(Duration::from_secs(60));
+        })
+        .await;
+        assert_eq!(connection.read_timeout(), Some(Duration::from_secs(60)));
+
+        connection.set_read_timeout(Some(Duration::from_millis(10)));
+        server.write_all(b"$3\r\nfo").await.unwrap();
+        assert!(matches!(
+            connection.read_frame().await,
+            Err(ConnectionError::Timeout)
+        ));
+
+        connection.set_read_timeout(None);
+        server.write_all(b"o\r\n").await.unwrap();
+        assert_eq!(
+            connection.read_frame().await.unwrap(),
+            Frame::String(Bytes::from("foo"))
+        )
+    }
+
+    #[tokio::test]
+    async fn last_used_advances_after_write_and_read() {
+        let (mut connection, mut server) = connection_pair().await;
+        let created = connection.last_used();
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        connection.write_frame(&Frame::Null).await.unwrap();
+        let written = connection.last_used();
+        assert!(written > created);
+
+        tokio::time::sleep(Duration::from_millis(5)).await;
+        server.write_all(b"%1\r\n").await.unwrap();
+        connection.read_frame().await.unwrap();
+        assert!(connection.last_used() > written)
+    }
+
+    #[tokio::test]
+    async fn read_frame_given_counted_pipeline_guard_catches_over_read() {
+        let (mut connection, mut server) = connection_pair().await;
+        connection.set_pipeline_guard(PipelineGuard::Counted);
+
+        connection.queue_frame(&Frame::Integer(1)).await.unwrap();
+        connection.queue_frame(&Frame::Integer(2)).await.unwrap();
+        connection.flush().await.unwrap();
+        assert_eq!(connection.pending_responses(), 2);
+
+        server.write_all(b"%1\r\n%2\r\n%3\r\n").await.unwrap();
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1));
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(2));
+        assert!(matches!(
+            connection.read_frame().await,
+            Err(ConnectionError::UnexpectedRead)
+        ));
+        assert_eq!(connection.pending_responses(), 0)
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_strict_pipeline_guard_fails_with_unread_responses() {
+        let (mut connection, mut server) = connection_pair().await;
+        connection.set_pipeline_guard(PipelineGuard::Strict);
+
+        connection.write_frame(&Frame::Integer(1)).await.unwrap();
+        assert!(matches!(
+            connection.write_frame(&Frame::Integer(2)).await,
+            Err(ConnectionError::UnreadResponses(1))
+        ));
+
+        server.write_all(b"%1\r\n").await.unwrap();
+        connection.read_frame().await.unwrap();
+        connection.write_frame(&Frame::Integer(2)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn write_frame_counted_returns_serialized_len_and_counts_bytes_written() {
+        let (mut connection, mut server) = connection_pair().await;
+        let frame = Frame::Array(vec![
+            Frame::String(Bytes::from("SET")),
+            Frame::String(Bytes::from("foo")),
+            Frame::Integer(42),
+        ]);
+
+        let written = connection.write_frame_counted(&frame).await.unwrap();
+        assert_eq!(written, frame.serialized_len());
+        connection.write_frame(&Frame::Null).await.unwrap();
+        assert_eq!(connection.bytes_written(), written as u64 + 3);
+
+        let mut received = vec![0; written + 3];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received[written..], b"-\r\n")
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_odd_length_map_returns_error_without_writing() {
+        let (mut connection, mut server) = connection_pair().await;
+        let frame = Frame::Array(vec![Frame::Map(vec![
+            Frame::String(Bytes::from("key")),
+            Frame::Integer(1),
+            Frame::String(Bytes::from("dangling")),
+        ])]);
+
+        assert!(matches!(
+            connection.write_frame(&frame).await,
+            Err(ConnectionError::UnpairedMapEntry)
+        ));
+        assert_eq!(connection.bytes_written(), 0);
+        connection.write_frame(&Frame::Null).await.unwrap();
+        let mut received = [0; 3];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"-\r\n")
+    }
+
+    #[tokio::test]
+    async fn encode_command_matches_bytes_sent_by_command() {
+        for dialect in [Dialect::Typed, Dialect::BulkStrings] {
+            let (mut connection, mut server) = connection_pair_with(|options| {
+                options.set_dialect(dialect);
+            })
+            .await;
+            let mut cmd = Command::new();
+            cmd.arg("set").arg("foo").arg(42);
+            let expected = frame::encode_command(
+                &[
+                    Frame::String(Bytes::from("set")),
+                    Frame::String(Bytes::from("foo")),
+                    Frame::Integer(42),
+                ],
+                dialect,
+            )
+            .unwrap();
+
+            let mut received = vec![0; expected.len()];
+            let (result, _) = tokio::join!(cmd.query_frame(&mut connection), async {
+                server.read_exact(&mut received).await.unwrap();
+                server.write_all(b"%1\r\n").await.unwrap();
+            });
+            result.unwrap();
+            assert_eq!(received, expected)
+        }
+    }
+
+    #[tokio::test]
+    async fn set_buffer_sizes_reads_back_new_sizes() {
+        let (connection, _server) = connection_pair().await;
+        let recv = connection.recv_buffer_size().unwrap();
+        let send = connection.send_buffer_size().unwrap();
+
+        connection.set_recv_buffer_size(recv * 2).unwrap();
+        connection.set_send_buffer_size(send * 2).unwrap();
+        assert!(connection.recv_buffer_size().unwrap() >= recv * 2);
+        assert!(connection.send_buffer_size().unwrap() >= send * 2)
+    }
+
+    #[tokio::test]
+    async fn write_raw_given_pipeline_guard_counts_each_frame() {
+        let (mut connection, _server) = connection_pair().await;
+        connection.set_pipeline_guard(PipelineGuard::Counted);
+
+        connection.write_raw(b"%1\r\n*1\r\n%2\r\n").await.unwrap();
+        assert_eq!(connection.pending_responses(), 2)
+    }
+
+    #[tokio::test]
+    async fn server_reset_sends_reset_and_skips_tracked_frames_ahead_of_reply() {
+        let (mut connection, mut server) = connection_pair().await;
+        connection.set_pipeline_guard(PipelineGuard::Counted);
+        connection.write_frame(&Frame::Integer(1)).await.unwrap();
+        server
+            .write_all(b"$3\r\nGET\r\n$5\r\nRESET\r\n")
+            .await
+            .unwrap();
+
+        connection.server_reset().await.unwrap();
+        assert_eq!(connection.pending_responses(), 0);
+        assert_eq!(connection.pipeline_guard(), PipelineGuard::Counted);
+        let mut received = [0; 19];
+        server.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"%1\r\n*1\r\n$5\r\nRESET\r\n")
+    }
+
+    #[tokio::test]
+    async fn server_reset_given_ok_reply_returns_ok() {
+        let (mut connection, mut server) = connection_pair().await;
+        server.write_all(b"$2\r\nOK\r\n%1\r\n").await.unwrap();
+
+        connection.server_reset().await.unwrap();
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1))
+    }
+
+    #[tokio::test]
+    async fn server_reset_given_untracked_reply_ahead_of_reset_returns_error() {
+        let (mut connection, mut server) = connection_pair().await;
+        server
+            .write_all(b"$3\r\nGET\r\n$5\r\nRESET\r\n")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            connection.server_reset().await,
+            Err(CommandError::IncompatibleType("frame::String", "RESET"))
+        ))
+    }
+
+    #[tokio::test]
+    async fn server_reset_given_tracked_error_reply_discards_it() {
+        let (mut connection, mut server) = connection_pair().await;
+        connection.set_pipeline_guard(PipelineGuard::Counted);
+        connection.write_frame(&Frame::Integer(1)).await.unwrap();
+        server
+            .write_all(b"!3\r\nERR\r\n$5\r\nRESET\r\n")
+            .await
+            .unwrap();
+
+        connection.server_reset().await.unwrap();
+        assert_eq!(connection.pending_responses(), 0)
+    }
+
+    #[tokio::test]
+    async fn server_reset_given_error_reply_returns_query_error() {
+        let (mut connection, mut server) = connection_pair().await;
+        server.write_all(b"!3\r\nERR\r\n").await.unwrap();
+
+        assert!(matches!(
+            connection.server_reset().await,
+            Err(CommandError::QueryError(message)) if message == "ERR"
+        ))
+    }
+
+    #[tokio::test]
+    async fn read_frame_or_timeout_given_no_data_returns_none_then_reads_frame() {
+        let (mut connection, mut server) = connection_pair().await;
+        server.write_all(b"$3\r\nf").await.unwrap();
+
+        assert_eq!(
+            connection
+                .read_frame_or_timeout(Duration::from_millis(10))
+                .await
+                .unwrap(),
+            None
+        );
+        server.write_all(b"oo\r\n").await.unwrap();
+        assert_eq!(
+            connection
+                .read_frame_or_timeout(Duration::from_secs(5))
+                .await
+                .unwrap(),
+            Some(Frame::String(Bytes::from("foo")))
+        )
+    }
+
+    #[tokio::test]
+    async fn write_frame_given_write_failing_partway_closes_connection() {
+        let (mut connection, mut server) = connection_pair().await;
+        connection.set_send_buffer_size(4096).unwrap();
+        connection.set_write_timeout(Some(Duration::from_millis(10)));
+
+        let frame = Frame::String(Bytes::from(vec![b'a'; 16 * 1024 * 1024]));
+        assert!(matches!(
+            connection.write_frame(&frame).await,
+            Err(ConnectionError::Timeout)
+        ));
+        assert!(connection.is_closed());
+        assert!(matches!(
+            connection.take_error(),
+            Some(ConnectionError::Timeout)
+        ));
+        assert!(connection.take_error().is_none());
+        assert!(matches!(
+            connection.write_frame(&Frame::Integer(1)).await,
+            Err(ConnectionError::Closed)
+        ));
+        server.write_all(b"%1\r\n").await.unwrap();
+        assert!(matches!(
+            connection.read_frame().await,
+            Err(ConnectionError::Closed)
+        ))
+    }
+
+    #[tokio::test]
+    async fn echo_given_binary_payload_round_trips_byte_for_byte() {
+        let server = TestServer::echo().await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        for payload in [&b""[..], b"\r\n", b"\0", b"$3\r\nfoo\r\n\0\xff"] {
+            assert_eq!(connection.echo(payload).await.unwrap(), payload)
+        }
+    }
+
+    #[tokio::test]
+    async fn echo_given_random_payloads_round_trips_byte_for_byte() {
+        use proptest::strategy::{Strategy, ValueTree};
+        use proptest::test_runner::TestRunner;
+
+        let server = TestServer::echo().await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+        let mut runner = TestRunner::deterministic();
+        for _ in 0..256 {
+            let payload = crate::strategies::bytes()
+                .new_tree(&mut runner)
+                .unwrap()
+                .current();
+            assert_eq!(connection.echo(&payload).await.unwrap(), payload)
+        }
+    }
+
+    #[tokio::test]
+    async fn drain_pending_given_unread_responses_returns_error() {
+        let (mut connection, mut server) = connection_pair().await;
+        connection.set_pipeline_guard(PipelineGuard::Counted);
+        connection.write_frame(&Frame::Integer(1)).await.unwrap();
+        server.write_all(b"%1\r\n").await.unwrap();
+
+        assert!(matches!(
+            connection.drain_pending(),
+            Err(ConnectionError::UnreadResponses(1))
+        ));
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1))
+    }
+
+    #[tokio::test]
+    async fn drain_pending_given_closed_connection_returns_closed() {
+        let (mut connection, _server) = connection_pair().await;
+        connection.set_send_buffer_size(4096).unwrap();
+        connection.set_write_timeout(Some(Duration::from_millis(10)));
+        let frame = Frame::String(Bytes::from(vec![b'a'; 16 * 1024 * 1024]));
+        connection.write_frame(&frame).await.unwrap_err();
+
+        assert!(matches!(
+            connection.drain_pending(),
+            Err(ConnectionError::Closed)
+        ))
+    }
+
+    #[tokio::test]
+    async fn drain_pending_given_buffered_pushes_returns_them_and_keeps_partial_frame() {
+        let (mut connection, mut server) = connection_pair().await;
+        server
+            .write_all(b"%1\r\n$4\r\npush\r\n%2\r\n$3\r\nfo")
+            .await
+            .unwrap();
+
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1));
+        assert_eq!(
+            connection.drain_pending().unwrap(),
+            vec![Frame::String(Bytes::from("push")), Frame::Integer(2)]
+        );
+        assert_eq!(connection.drain_pending().unwrap(), vec![]);
+
+        server.write_all(b"o\r\n").await.unwrap();
+        assert_eq!(
+            connection.read_frame().await.unwrap(),
+            Frame::String(Bytes::from("foo"))
+        )
+    }
+
+    #[tokio::test]
+    async fn read_frame_given_attribute_returns_reply_and_exposes_attribute() {
+        let (mut connection, mut server) = connection_pair().await;
+        server
+            .write_all(b"|1\r\n$3\r\nttl\r\n%10\r\n$3\r\nbar\r\n%1\r\n")
+            .await
+            .unwrap();
+
+        let reply = connection.read_frame().await.unwrap();
+        assert_eq!(reply, Frame::String(Bytes::from("bar")));
+        assert_eq!(
+            connection.take_attributes(),
+            Some(vec![Frame::String(Bytes::from("ttl")), Frame::Integer(10)])
+        );
+        assert_eq!(connection.take_attributes(), None);
+
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1));
+        assert_eq!(connection.take_attributes(), None)
+    }
+
+    #[tokio::test]
+    async fn read_frame_or_timeout_given_attribute_split_from_reply_keeps_attribute() {
+        let (mut connection, mut server) = connection_pair().await;
+        server
+            .write_all(b"|1\r\n$3\r\nttl\r\n%10\r\n$3\r\nb")
+            .await
+            .unwrap();
+
+        let timeout = Duration::from_millis(10);
+        assert_eq!(
+            connection.read_frame_or_timeout(timeout).await.unwrap(),
+            None
+        );
+        assert_eq!(connection.take_attributes(), None);
+
+        server.write_all(b"ar\r\n").await.unwrap();
+        assert_eq!(
+            connection.read_frame_or_timeout(timeout).await.unwrap(),
+            Some(Frame::String(Bytes::from("bar")))
+        );
+        assert_eq!(
+            connection.take_attributes(),
+            Some(vec![Frame::String(Bytes::from("ttl")), Frame::Integer(10)])
+        )
+    }
+
+    #[tokio::test]
+    async fn read_frame_given_frame_exceeding_max_frame_size_returns_frame_too_large_error() {
+        let (mut connection, mut server) = connection_pair_with(|options| {
+            options.set_max_frame_size(8);
+        })
+        .await;
+        server
+            .write_all(b"$?\r\n;3\r\nfoo\r\n;3\r\n")
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            connection.read_frame().await,
+            Err(ConnectionError::FrameError(ParseFrameError::FrameTooLarge))
+        ))
+    }
+
+    #[tokio::test]
+    async fn read_frame_given_array_exceeding_max_elements_returns_frame_too_large_error() {
+        let (mut connection, mut server) = connection_pair_with(|options| {
+            options.set_max_elements(2);
+        })
+        .await;
+        server.write_all(b"*3\r\n%1\r\n%2\r\n%3\r\n").await.unwrap();
+
+        assert!(matches!(
+            connection.read_frame().await,
+            Err(ConnectionError::FrameError(ParseFrameError::FrameTooLarge))
+        ))
+    }
+
+    #[tokio::test]
+    async fn command_given_string_args_decodes_result() {
+        let server = TestServer::start(|frame| match frame {
+            Frame::Array(args) => Frame::Integer(args.len() as i64),
+            _ => Frame::Null,
+        })
+        .await;
+        let mut connection = Connection::connect(&server.options()).await.unwrap();
+
+        let len: i64 = connection.command(&["SET", "foo", "bar"]).await.unwrap();
+        assert_eq!(len, 3)
+    }
 }
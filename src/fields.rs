@@ -0,0 +1,167 @@
+use crate::command::{incompatible_type, CommandError, FromSegmentFrame};
+use crate::frame::Frame;
+
+/// Controls how a field which is missing from a map is decoded by `MapFields::field`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MissingField {
+    /// Fails with `CommandError::MissingField`
+    #[default]
+    Error,
+
+    /// Decodes the field as if the server had sent a null, so `Option` fields become `None`
+    /// while other fields fail with `CommandError::UnexpectedNull`
+    Null,
+}
+
+/// Looks up the fields of a map with string keys when decoding it into a struct.
+/// `FromSegmentFrame` implementations for structs call `field` for each field, or `field_or_default`
+/// for fields which fall back to `Default` when missing
+#[derive(Debug, Clone, Copy)]
+pub struct MapFields<'a> {
+    entries: &'a [Frame],
+    missing: MissingField,
+}
+
+impl<'a> MapFields<'a> {
+    /// Creates the fields of a map frame, missing fields are handled according to the given policy
+    pub fn new(frame: &'a Frame, missing: MissingField) -> Result<Self, CommandError> {
+        match frame {
+            Frame::Map(entries) => Ok(MapFields { entries, missing }),
+            other => Err(incompatible_type::<Self>(other)),
+        }
+    }
+
+    /// Returns the value of the first entry with the given string key
+    pub fn get(&self, key: &str) -> Option<&'a Frame> {
+        self.entries
+            .chunks_exact(2)
+            .find_map(|pair| match &pair[0] {
+                Frame::String(candidate) if candidate == key.as_bytes() => Some(&pair[1]),
+                _ => None,
+            })
+    }
+
+    /// Decodes the field with the given key, a missing field is handled according to the policy
+    pub fn field<T: FromSegmentFrame>(&self, key: &str) -> Result<T, CommandError> {
+        match (self.get(key), self.missing) {
+            (Some(value), _) => T::from_segment_frame(value),
+            (None, MissingField::Error) => Err(CommandError::MissingField(key.to_string())),
+            (None, MissingField::Null) => T::from_segment_frame(&Frame::Null),
+        }
+    }
+
+    /// Decodes the field with the given key, a missing field is decoded as `T::default()` regardless of the policy
+    pub fn field_or_default<T: FromSegmentFrame + Default>(
+        &self,
+        key: &str,
+    ) -> Result<T, CommandError> {
+        match self.get(key) {
+            Some(value) => T::from_segment_frame(value),
+            None => Ok(T::default()),
+        }
+    }
+
+    /// Returns the policy for missing fields
+    pub fn missing(&self) -> MissingField {
+        self.missing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[derive(Debug, PartialEq)]
+    struct User {
+        name: String,
+        email: Option<String>,
+        // Falls back to its default when missing
+        visits: i64,
+    }
+
+    fn decode_user(frame: &Frame, missing: MissingField) -> Result<User, CommandError> {
+        let fields = MapFields::new(frame, missing)?;
+        Ok(User {
+            name: fields.field("name")?,
+            email: fields.field("email")?,
+            visits: fields.field_or_default("visits")?,
+        })
+    }
+
+    fn user_map(entries: &[(&'static str, Frame)]) -> Frame {
+        Frame::Map(
+            entries
+                .iter()
+                .flat_map(|(key, value)| [Frame::String(Bytes::from(*key)), value.clone()])
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn field_given_error_policy_and_missing_option_returns_missing_field_error() {
+        let frame = user_map(&[("name", Frame::String(Bytes::from("foo")))]);
+        assert!(matches!(
+            decode_user(&frame, MissingField::Error),
+            Err(CommandError::MissingField(key)) if key == "email"
+        ))
+    }
+
+    #[test]
+    fn field_given_null_policy_and_missing_option_returns_none() {
+        let frame = user_map(&[("name", Frame::String(Bytes::from("foo")))]);
+        assert_eq!(
+            decode_user(&frame, MissingField::Null).unwrap(),
+            User {
+                name: "foo".to_string(),
+                email: None,
+                visits: 0,
+            }
+        )
+    }
+
+    #[test]
+    fn field_given_null_policy_and_missing_required_field_returns_unexpected_null_error() {
+        let frame = user_map(&[("email", Frame::Null)]);
+        assert!(matches!(
+            decode_user(&frame, MissingField::Null),
+            Err(CommandError::UnexpectedNull(_))
+        ))
+    }
+
+    #[test]
+    fn field_or_default_given_missing_field_returns_default_under_every_policy() {
+        let frame = user_map(&[
+            ("name", Frame::String(Bytes::from("foo"))),
+            ("email", Frame::String(Bytes::from("foo@example.com"))),
+        ]);
+        for missing in [MissingField::Error, MissingField::Null] {
+            assert_eq!(decode_user(&frame, missing).unwrap().visits, 0)
+        }
+    }
+
+    #[test]
+    fn field_given_present_fields_decodes_them() {
+        let frame = user_map(&[
+            ("visits", Frame::Integer(3)),
+            ("email", Frame::String(Bytes::from("foo@example.com"))),
+            ("name", Frame::String(Bytes::from("foo"))),
+        ]);
+        assert_eq!(
+            decode_user(&frame, MissingField::Error).unwrap(),
+            User {
+                name: "foo".to_string(),
+                email: Some("foo@example.com".to_string()),
+                visits: 3,
+            }
+        )
+    }
+
+    #[test]
+    fn new_given_non_map_frame_returns_incompatible_type_error() {
+        assert!(matches!(
+            MapFields::new(&Frame::Integer(1), MissingField::Error),
+            Err(CommandError::IncompatibleType("frame::Integer", _))
+        ))
+    }
+}
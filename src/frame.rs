@@ -1,7 +1,13 @@
+use crate::command::{self, CommandError, FromSegmentFrame};
+use crate::connection::Dialect;
 use atoi::atoi;
-use bytes::Buf;
-use bytes::Bytes;
+use bytes::{Buf, BufMut};
+use bytes::{Bytes, BytesMut};
+use std::any::type_name;
+use std::cell::Cell;
+use std::hash::{Hash, Hasher};
 use std::io::Cursor;
+use std::mem;
 use std::str;
 use thiserror::Error;
 
@@ -21,9 +27,16 @@ pub const MAP_IDENT: u8 = b'#';
 pub const DOUBLE_IDENT: u8 = b'.';
 /// Identifier for error type
 pub const ERROR_IDENT: u8 = b'!';
-
-/// Represents a Segment protocol frame
-#[derive(Debug, PartialEq)]
+/// Identifier for attribute type
+pub const ATTRIBUTE_IDENT: u8 = b'|';
+/// Identifier for a chunk of a streamed string
+pub const CHUNK_IDENT: u8 = b';';
+
+/// Represents a Segment protocol frame.
+/// Frames are compared and hashed structurally, with doubles compared by their bit pattern so `Frame` can be
+/// used as a key in maps and sets. A NaN double is equal to the same NaN, and `0.0` is equal to `-0.0` like with `f64`.
+/// Map entries are compared in order, use `logically_eq` to compare maps regardless of their order
+#[derive(Debug, Clone)]
 pub enum Frame {
     /// Represents a string frame which is a binary safe string
     String(Bytes),
@@ -41,6 +54,115 @@ pub enum Frame {
     Double(f64),
     /// Represents a error frame which is a binary safe string
     Error(Bytes),
+    /// Represents an attribute frame which holds out of band metadata for the reply that follows it.
+    /// Like a map, a Vec of alternating keys and values is used to represent it
+    Attribute(Vec<Frame>),
+}
+
+impl PartialEq for Frame {
+    fn eq(&self, other: &Frame) -> bool {
+        match (self, other) {
+            (Frame::String(a), Frame::String(b)) => a == b,
+            (Frame::Integer(a), Frame::Integer(b)) => a == b,
+            (Frame::Array(a), Frame::Array(b)) => a == b,
+            (Frame::Boolean(a), Frame::Boolean(b)) => a == b,
+            (Frame::Null, Frame::Null) => true,
+            (Frame::Map(a), Frame::Map(b)) => a == b,
+            (Frame::Double(a), Frame::Double(b)) => double_bits(*a) == double_bits(*b),
+            (Frame::Error(a), Frame::Error(b)) => a == b,
+            (Frame::Attribute(a), Frame::Attribute(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for Frame {}
+
+impl Hash for Frame {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            Frame::String(data) | Frame::Error(data) => data.hash(state),
+            Frame::Integer(val) => val.hash(state),
+            Frame::Array(frames) | Frame::Map(frames) | Frame::Attribute(frames) => {
+                frames.hash(state)
+            }
+            Frame::Boolean(val) => val.hash(state),
+            Frame::Null => {}
+            Frame::Double(val) => double_bits(*val).hash(state),
+        }
+    }
+}
+
+/// Returns the bit pattern doubles are compared and hashed by, with `-0.0` canonicalized to `0.0`
+fn double_bits(val: f64) -> u64 {
+    if val == 0.0 {
+        0
+    } else {
+        val.to_bits()
+    }
+}
+
+/// Receives the values of a frame as it is parsed, so a response can be processed without the frame being built.
+/// Scalars which are not overridden fail the same way a `FromSegmentFrame` conversion would, while collections
+/// are accepted so only the scalars inside them need handling. Attributes are out of band metadata and are not visited.
+/// Once a method fails, the rest of the frame is skipped and the error is returned
+pub trait FrameVisitor {
+    /// Visits a string
+    fn visit_string(&mut self, _data: &[u8]) -> Result<(), CommandError> {
+        Err(unexpected_frame::<Self>("frame::String"))
+    }
+
+    /// Visits an integer
+    fn visit_integer(&mut self, _val: i64) -> Result<(), CommandError> {
+        Err(unexpected_frame::<Self>("frame::Integer"))
+    }
+
+    /// Visits a boolean
+    fn visit_boolean(&mut self, _val: bool) -> Result<(), CommandError> {
+        Err(unexpected_frame::<Self>("frame::Boolean"))
+    }
+
+    /// Visits a null
+    fn visit_null(&mut self) -> Result<(), CommandError> {
+        Err(CommandError::UnexpectedNull(type_name::<Self>()))
+    }
+
+    /// Visits a double
+    fn visit_double(&mut self, _val: f64) -> Result<(), CommandError> {
+        Err(unexpected_frame::<Self>("frame::Double"))
+    }
+
+    /// Visits an error returned by the server
+    fn visit_error(&mut self, data: &[u8]) -> Result<(), CommandError> {
+        Err(CommandError::QueryError(
+            String::from_utf8_lossy(data).into_owned(),
+        ))
+    }
+
+    /// Visits the start of an array with the given number of elements, the elements are visited next
+    fn start_array(&mut self, _len: usize) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    /// Visits the end of an array
+    fn end_array(&mut self) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    /// Visits the start of a map with the given number of entries, the keys and values are visited next in turn
+    fn start_map(&mut self, _len: usize) -> Result<(), CommandError> {
+        Ok(())
+    }
+
+    /// Visits the end of a map
+    fn end_map(&mut self) -> Result<(), CommandError> {
+        Ok(())
+    }
+}
+
+fn unexpected_frame<V: ?Sized>(frame_type: &'static str) -> CommandError {
+    CommandError::IncompatibleType(frame_type, type_name::<V>())
 }
 
 /// Represents frame parsing error
@@ -50,9 +172,79 @@ pub enum ParseFrameError {
     #[error("incomplete frame")]
     Incomplete,
 
-    /// Occurs when we receive a malformed frame
-    #[error("invalid frame format")]
-    InvalidFormat,
+    /// Occurs when we receive a malformed frame. Holds what was being parsed and the byte offset of the frame in the buffer
+    #[error("invalid {context} at offset {offset}")]
+    InvalidFormat {
+        /// The kind of value that failed to parse
+        context: &'static str,
+        /// Byte offset of the malformed frame from the start of the buffer
+        offset: usize,
+    },
+
+    /// Occurs when a frame exceeds the maximum frame size
+    #[error("frame exceeds the maximum frame size")]
+    FrameTooLarge,
+}
+
+/// Represents a structural error found by `Frame::validate`
+#[derive(Debug, Error, PartialEq)]
+pub enum FrameError {
+    /// Occurs when a map or attribute frame has an odd number of elements
+    #[error("map frame has an odd number of elements")]
+    UnpairedMapEntry,
+
+    /// Occurs when the frame contains more elements across all its collections than the maximum
+    #[error("frame exceeds the maximum number of elements")]
+    TooManyElements,
+
+    /// Occurs when the serialized frame exceeds the maximum frame size
+    #[error("frame exceeds the maximum frame size")]
+    FrameTooLarge,
+}
+
+/// Represents the limits applied while parsing frames
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameLimits {
+    max_frame_size: usize,
+    max_elements: usize,
+}
+
+impl FrameLimits {
+    /// Creates limits which do not restrict the frames
+    pub fn new() -> Self {
+        FrameLimits {
+            max_frame_size: usize::MAX,
+            max_elements: usize::MAX,
+        }
+    }
+
+    /// Sets the maximum number of bytes a single frame, including all its nested frames, can take on the wire
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) -> &mut Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Returns the maximum number of bytes a single frame can take on the wire
+    pub fn max_frame_size(&self) -> usize {
+        self.max_frame_size
+    }
+
+    /// Sets the maximum number of elements a single frame can contain across all its nested arrays, maps and attributes
+    pub fn set_max_elements(&mut self, max_elements: usize) -> &mut Self {
+        self.max_elements = max_elements;
+        self
+    }
+
+    /// Returns the maximum number of elements a single frame can contain
+    pub fn max_elements(&self) -> usize {
+        self.max_elements
+    }
+}
+
+impl Default for FrameLimits {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Frame {
@@ -67,31 +259,317 @@ impl Frame {
             Frame::Map(_) => {"frame::Map"}
             Frame::Double(_) => {"frame::Double"}
             Frame::Error(_) => {"frame::Error"}
+            Frame::Attribute(_) => {"frame::Attribute"}
+        }
+    }
+
+    /// Returns the number of elements in an array, the number of pairs in a map and the number of bytes in a string or an error.
+    /// Returns None for scalar frames
+    pub fn len(&self) -> Option<usize> {
+        match self {
+            Frame::String(data) | Frame::Error(data) => Some(data.len()),
+            Frame::Array(array) => Some(array.len()),
+            Frame::Map(map) | Frame::Attribute(map) => Some(map.len() / 2),
+            _ => None,
+        }
+    }
+
+    /// Returns the number of bytes the frame takes on the wire once serialized, without serializing it
+    pub fn serialized_len(&self) -> usize {
+        match self {
+            Frame::String(data) | Frame::Error(data) => line_len(data.len()) + data.len() + 2,
+            Frame::Integer(val) => line_len(val),
+            Frame::Boolean(_) => 4,
+            Frame::Null => 3,
+            Frame::Double(val) => line_len(val),
+            Frame::Array(children) => {
+                line_len(children.len()) + children.iter().map(Frame::serialized_len).sum::<usize>()
+            }
+            Frame::Map(children) | Frame::Attribute(children) => {
+                line_len(children.len() / 2)
+                    + children.iter().map(Frame::serialized_len).sum::<usize>()
+            }
+        }
+    }
+
+    /// Checks that the frame can be written and read back under the limits: maps have an even number of elements,
+    /// the elements across all nested collections do not exceed the maximum and the serialized frame fits the
+    /// maximum frame size. The elements are counted the same way the parser counts them
+    pub fn validate(&self, limits: &FrameLimits) -> Result<(), FrameError> {
+        let mut elements = limits.max_elements();
+        self.validate_elements(&mut elements)?;
+        if self.serialized_len() > limits.max_frame_size() {
+            return Err(FrameError::FrameTooLarge);
+        }
+        Ok(())
+    }
+
+    fn validate_elements(&self, remaining: &mut usize) -> Result<(), FrameError> {
+        let children = match self {
+            Frame::Map(children) | Frame::Attribute(children) if children.len() % 2 != 0 => {
+                return Err(FrameError::UnpairedMapEntry)
+            }
+            Frame::Array(children) | Frame::Map(children) | Frame::Attribute(children) => children,
+            _ => return Ok(()),
+        };
+        *remaining = remaining
+            .checked_sub(children.len())
+            .ok_or(FrameError::TooManyElements)?;
+        children
+            .iter()
+            .try_for_each(|child| child.validate_elements(remaining))
+    }
+
+    /// Renders the frame as a string of roughly `max_bytes` for logging. Strings, arrays and maps which do not fit
+    /// are cut short with an ellipsis followed by the number of bytes or elements, so the output only goes over
+    /// `max_bytes` by these markers and the closing brackets
+    pub fn truncate_for_log(&self, max_bytes: usize) -> String {
+        let mut out = String::new();
+        write_for_log(self, &mut out, max_bytes);
+        out
+    }
+
+    /// Returns true if the frame has a length of zero. Returns None for scalar frames
+    pub fn is_empty(&self) -> Option<bool> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Returns an iterator over the child frames of an array, map or attribute frame.
+    /// Map and attribute children alternate between keys and values. Scalar frames yield nothing
+    pub fn iter(&self) -> std::slice::Iter<'_, Frame> {
+        match self {
+            Frame::Array(children) | Frame::Map(children) | Frame::Attribute(children) => {
+                children.iter()
+            }
+            _ => [].iter(),
+        }
+    }
+
+    /// Returns the message of an error frame without copying it. Returns None for other frames
+    pub fn error_message(&self) -> Option<&[u8]> {
+        match self {
+            Frame::Error(data) => Some(data),
+            _ => None,
+        }
+    }
+
+    /// Returns the code of an error frame, which is the message up to the first space, without copying it.
+    /// Returns None for other frames
+    pub fn error_code(&self) -> Option<&[u8]> {
+        self.error_message()
+            .map(|message| message.split(|b| *b == b' ').next().unwrap_or(message))
+    }
+
+    /// Returns the value of the first map entry whose key decodes to the given key.
+    /// Keys which can not be decoded into `K` are skipped. Returns None if the frame is not a map or the key is not found
+    pub fn map_entry<K: PartialEq + FromSegmentFrame>(&self, key: &K) -> Option<&Frame> {
+        match self {
+            Frame::Map(map) => {
+                map.chunks_exact(2)
+                    .find_map(|pair| match K::from_segment_frame(&pair[0]) {
+                        Ok(candidate) if candidate == *key => Some(&pair[1]),
+                        _ => None,
+                    })
+            }
+            _ => None,
+        }
+    }
+
+    /// Compares the frames by their logical value. Unlike `==`, an integer and a double holding the same number
+    /// are equal and map and attribute entries are compared regardless of their order
+    pub fn logically_eq(&self, other: &Frame) -> bool {
+        match (self, other) {
+            (Frame::Integer(int), Frame::Double(double))
+            | (Frame::Double(double), Frame::Integer(int)) => integer_eq_double(*int, *double),
+            (Frame::Array(a), Frame::Array(b)) => {
+                a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.logically_eq(b))
+            }
+            (Frame::Map(a), Frame::Map(b)) | (Frame::Attribute(a), Frame::Attribute(b)) => {
+                entries_logically_eq(a, b)
+            }
+            _ => self == other,
+        }
+    }
+
+    /// Converts the frame to a JSON value.
+    /// Strings are converted to JSON strings if they are valid UTF-8, otherwise they are base64 encoded.
+    /// Errors are converted to an object of the form `{"error": "<message>"}`.
+    /// Maps are converted to JSON objects, string keys are used as is and other keys are converted to their JSON text.
+    /// Doubles which can not be represented in JSON (NaN and infinities) are converted to null
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> serde_json::Value {
+        use serde_json::Value;
+
+        match self {
+            Frame::String(data) => Value::String(bytes_to_json_string(data)),
+            Frame::Integer(data) => Value::from(*data),
+            Frame::Array(array) => Value::Array(array.iter().map(Frame::to_json).collect()),
+            Frame::Boolean(data) => Value::Bool(*data),
+            Frame::Null => Value::Null,
+            Frame::Map(map) | Frame::Attribute(map) => {
+                let mut object = serde_json::Map::with_capacity(map.len() / 2);
+                for pair in map.chunks(2) {
+                    let key = match pair[0].to_json() {
+                        Value::String(key) => key,
+                        other => other.to_string(),
+                    };
+                    let value = pair.get(1).map_or(Value::Null, Frame::to_json);
+                    object.insert(key, value);
+                }
+                Value::Object(object)
+            }
+            Frame::Double(data) => {
+                serde_json::Number::from_f64(*data).map_or(Value::Null, Value::Number)
+            }
+            Frame::Error(data) => {
+                let mut object = serde_json::Map::with_capacity(1);
+                object.insert(
+                    "error".to_string(),
+                    Value::String(bytes_to_json_string(data)),
+                );
+                Value::Object(object)
+            }
         }
     }
 }
 
+#[cfg(feature = "json")]
+fn bytes_to_json_string(data: &Bytes) -> String {
+    use base64::Engine;
+
+    match str::from_utf8(data) {
+        Ok(data) => data.to_string(),
+        Err(_) => base64::engine::general_purpose::STANDARD.encode(data),
+    }
+}
+
+fn integer_eq_double(int: i64, double: f64) -> bool {
+    // i64::MAX is not representable as a double, the cast rounds it up to the first value out of range
+    double.fract() == 0.0
+        && double >= i64::MIN as f64
+        && double < i64::MAX as f64
+        && double as i64 == int
+}
+
+/// Compares the key value pairs of two maps regardless of their order, each pair is matched at most once
+fn entries_logically_eq(a: &[Frame], b: &[Frame]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let mut matched = vec![false; b.len() / 2];
+    a.chunks_exact(2).all(|pair| {
+        let found = b.chunks_exact(2).enumerate().position(|(i, candidate)| {
+            !matched[i]
+                && pair[0].logically_eq(&candidate[0])
+                && pair[1].logically_eq(&candidate[1])
+        });
+        match found {
+            Some(i) => {
+                matched[i] = true;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
 /// Parses the buffered data into frames
 pub fn parse(buf: &mut Cursor<&[u8]>) -> Result<Frame, ParseFrameError> {
+    parse_with_limits(buf, &FrameLimits::default())
+}
+
+/// Parses the buffered data into frames, failing if the frame exceeds the given limits
+pub fn parse_with_limits(
+    buf: &mut Cursor<&[u8]>,
+    limits: &FrameLimits,
+) -> Result<Frame, ParseFrameError> {
+    parse_head_with_limits(buf, limits, usize::MAX)
+}
+
+/// Parses the buffered data into frames like `parse_with_limits`, but if the frame is an array only its first
+/// `head` elements are built, the remaining elements are skipped over without materializing them.
+/// Frames other than arrays are parsed as is
+pub fn parse_head_with_limits(
+    buf: &mut Cursor<&[u8]>,
+    limits: &FrameLimits,
+    head: usize,
+) -> Result<Frame, ParseFrameError> {
+    let ctx = ParseContext {
+        limits,
+        start: buf.position() as usize,
+        elements: Cell::new(limits.max_elements()),
+    };
+
+    let offset = buf.position() as usize;
+    let line = get_line(buf)?;
+    if line.first() == Some(&ARRAY_IDENT) {
+        return parse_array_head(buf, &line[1..], offset, &ctx, head);
+    }
+    buf.set_position(offset as u64);
+    parse_frame(buf, &ctx)
+}
+
+struct ParseContext<'a> {
+    limits: &'a FrameLimits,
+    start: usize,
+    /// The number of elements nested collections can still declare before the frame is too large
+    elements: Cell<usize>,
+}
+
+impl ParseContext<'_> {
+    /// Fails if a frame which ends at the given position in the buffer would exceed the maximum frame size
+    fn check_size(&self, end: usize) -> Result<(), ParseFrameError> {
+        if end.saturating_sub(self.start) > self.limits.max_frame_size() {
+            return Err(ParseFrameError::FrameTooLarge);
+        }
+        Ok(())
+    }
+
+    /// Takes the declared number of elements from the budget shared by all collections in the frame
+    fn take_elements(&self, count: usize) -> Result<(), ParseFrameError> {
+        let remaining = self
+            .elements
+            .get()
+            .checked_sub(count)
+            .ok_or(ParseFrameError::FrameTooLarge)?;
+        self.elements.set(remaining);
+        Ok(())
+    }
+}
+
+fn parse_frame(buf: &mut Cursor<&[u8]>, ctx: &ParseContext) -> Result<Frame, ParseFrameError> {
+    let offset = buf.position() as usize;
     let line = get_line(buf)?;
     if line.is_empty() {
-        return Err(ParseFrameError::InvalidFormat);
+        return Err(invalid_format("frame", offset));
     }
     let frame_type = line[0];
     let line = &line[1..];
     match frame_type {
-        STRING_IDENT => parse_string(buf, line),
-        INTEGER_IDENT => parse_integer(line),
-        ARRAY_IDENT => parse_array(buf, line),
-        BOOLEAN_IDENT => parse_boolean(line),
-        NULL_IDENT => parse_null(line),
-        MAP_IDENT => parse_map(buf, line),
-        DOUBLE_IDENT => parse_double(line),
-        ERROR_IDENT => parse_error(buf, line),
-        _ => Err(ParseFrameError::InvalidFormat),
+        STRING_IDENT => parse_string(buf, line, offset, ctx),
+        INTEGER_IDENT => parse_integer(line, offset),
+        ARRAY_IDENT => parse_array(buf, line, offset, ctx),
+        BOOLEAN_IDENT => parse_boolean(line, offset),
+        NULL_IDENT => parse_null(line, offset),
+        MAP_IDENT => parse_map(buf, line, offset, ctx),
+        DOUBLE_IDENT => parse_double(line, offset),
+        ERROR_IDENT => parse_error(buf, line, offset, ctx),
+        ATTRIBUTE_IDENT => Ok(Frame::Attribute(parse_pairs(
+            buf,
+            line,
+            offset,
+            ctx,
+            "attribute length",
+        )?)),
+        _ => Err(invalid_format("frame type", offset)),
     }
 }
 
+fn invalid_format(context: &'static str, offset: usize) -> ParseFrameError {
+    ParseFrameError::InvalidFormat { context, offset }
+}
+
 fn get_line<'a>(buf: &mut Cursor<&'a [u8]>) -> Result<&'a [u8], ParseFrameError> {
     if !buf.has_remaining() {
         return Err(ParseFrameError::Incomplete);
@@ -118,97 +596,571 @@ fn skip(buf: &mut Cursor<&[u8]>, n: usize) -> Result<(), ParseFrameError> {
     Ok(())
 }
 
-fn parse_string(buf: &mut Cursor<&[u8]>, line: &[u8]) -> Result<Frame, ParseFrameError> {
-    let len = atoi::<usize>(line).ok_or(ParseFrameError::InvalidFormat)?;
+fn parse_string(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+) -> Result<Frame, ParseFrameError> {
+    if line == b"?" {
+        return parse_streamed_string(buf, ctx);
+    }
+
+    Ok(Frame::String(parse_blob(
+        buf,
+        line,
+        offset,
+        ctx,
+        "string length",
+    )?))
+}
+
+/// Parses a string of unknown length which is sent as `$?\r\n` followed by chunks of the form `;<len>\r\n<data>\r\n`
+/// and terminated by an empty `;0\r\n` chunk
+fn parse_streamed_string(
+    buf: &mut Cursor<&[u8]>,
+    ctx: &ParseContext,
+) -> Result<Frame, ParseFrameError> {
+    let mut data = BytesMut::new();
+    read_chunks(buf, ctx, |chunk| data.extend_from_slice(chunk))?;
+    Ok(Frame::String(data.freeze()))
+}
+
+/// Reads the chunks of a streamed string up to and including the terminating chunk, passing each payload to `on_chunk`
+fn read_chunks(
+    buf: &mut Cursor<&[u8]>,
+    ctx: &ParseContext,
+    mut on_chunk: impl FnMut(&[u8]),
+) -> Result<(), ParseFrameError> {
+    loop {
+        let chunk_offset = buf.position() as usize;
+        let line = get_line(buf)?;
+        if line.first() != Some(&CHUNK_IDENT) {
+            return Err(invalid_format("string chunk", chunk_offset));
+        }
+        let len = atoi::<usize>(&line[1..])
+            .ok_or_else(|| invalid_format("string chunk length", chunk_offset))?;
+        if len == 0 {
+            return Ok(());
+        }
+        let n = len
+            .checked_add(2)
+            .ok_or_else(|| invalid_format("string chunk length", chunk_offset))?;
+        ctx.check_size(buf.position() as usize + n)?;
+
+        if buf.remaining() < n {
+            return Err(ParseFrameError::Incomplete);
+        }
+
+        check_delimiter(buf, len, chunk_offset)?;
+        on_chunk(&buf.chunk()[..len]);
+
+        skip(buf, n)?;
+    }
+}
+
+fn check_delimiter(buf: &Cursor<&[u8]>, len: usize, offset: usize) -> Result<(), ParseFrameError> {
+    if &buf.chunk()[len..len + 2] != b"\r\n" {
+        return Err(invalid_format("delimiter", offset));
+    }
+    Ok(())
+}
+
+fn parse_blob(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+    context: &'static str,
+) -> Result<Bytes, ParseFrameError> {
+    let len = check_blob(buf, line, offset, ctx, context)?;
     let n = len + 2;
 
-    if buf.remaining() < n {
-        return Err(ParseFrameError::Incomplete);
+    if len == 0 {
+        // Only the trailing delimiter follows an empty payload
+        skip(buf, n)?;
+        return Ok(Bytes::new());
     }
 
     let data = Bytes::copy_from_slice(&buf.chunk()[..len]);
 
     skip(buf, n)?;
 
-    Ok(Frame::String(data))
+    Ok(data)
 }
 
-fn parse_integer(line: &[u8]) -> Result<Frame, ParseFrameError> {
-    let int = atoi::<i64>(line).ok_or(ParseFrameError::InvalidFormat)?;
+/// Checks that the payload declared by the line is fully buffered and followed by a delimiter, returning its length
+fn check_blob(
+    buf: &Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+    context: &'static str,
+) -> Result<usize, ParseFrameError> {
+    let len = atoi::<usize>(line).ok_or_else(|| invalid_format(context, offset))?;
+    let n = len
+        .checked_add(2)
+        .ok_or_else(|| invalid_format(context, offset))?;
+    ctx.check_size(buf.position() as usize + n)?;
+
+    if buf.remaining() < n {
+        return Err(ParseFrameError::Incomplete);
+    }
+
+    // The payload is binary safe, so the declared length is trusted and the delimiter is checked to make sure
+    // a wrong length is reported instead of desyncing the frames that follow
+    check_delimiter(buf, len, offset)?;
+
+    Ok(len)
+}
+
+fn parse_integer(line: &[u8], offset: usize) -> Result<Frame, ParseFrameError> {
+    let int = atoi::<i64>(line).ok_or_else(|| invalid_format("integer", offset))?;
     Ok(Frame::Integer(int))
 }
 
-fn parse_array(buf: &mut Cursor<&[u8]>, line: &[u8]) -> Result<Frame, ParseFrameError> {
-    let len = atoi::<usize>(line).ok_or(ParseFrameError::InvalidFormat)?;
-    let mut vec = Vec::with_capacity(len);
-    for _ in 0..len {
-        vec.push(parse(buf)?);
+fn parse_array(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+) -> Result<Frame, ParseFrameError> {
+    parse_array_head(buf, line, offset, ctx, usize::MAX)
+}
+
+fn parse_array_head(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+    head: usize,
+) -> Result<Frame, ParseFrameError> {
+    let len = atoi::<usize>(line).ok_or_else(|| invalid_format("array length", offset))?;
+    ctx.take_elements(len)?;
+    // Every element takes at least 3 bytes, so the declared length is never trusted beyond what is buffered
+    let mut vec = Vec::with_capacity(len.min(head).min(buf.remaining()));
+    for i in 0..len {
+        if i < head {
+            vec.push(parse_frame(buf, ctx)?);
+        } else {
+            skip_frame(buf, ctx)?;
+        }
     }
 
     Ok(Frame::Array(vec))
 }
 
-fn parse_boolean(line: &[u8]) -> Result<Frame, ParseFrameError> {
-    if line.len() > 1 {
-        return Err(ParseFrameError::InvalidFormat);
+/// Checks that a complete and valid frame is buffered and advances past it without building it
+pub(crate) fn check_with_limits(
+    buf: &mut Cursor<&[u8]>,
+    limits: &FrameLimits,
+) -> Result<(), ParseFrameError> {
+    let ctx = ParseContext {
+        limits,
+        start: buf.position() as usize,
+        elements: Cell::new(limits.max_elements()),
+    };
+    skip_frame(buf, &ctx)
+}
+
+/// Drives the visitor over a frame which was already checked with `check_with_limits`, advancing past it.
+/// If the visitor fails, the rest of the frame is still advanced past so the buffer ends at a frame boundary
+pub(crate) fn visit<V: FrameVisitor + ?Sized>(
+    buf: &mut Cursor<&[u8]>,
+    visitor: &mut V,
+) -> Result<(), CommandError> {
+    let limits = FrameLimits::new();
+    let ctx = ParseContext {
+        limits: &limits,
+        start: buf.position() as usize,
+        elements: Cell::new(limits.max_elements()),
+    };
+    let mut visit = Visit {
+        visitor,
+        result: Ok(()),
+    };
+    visit_frame(buf, &ctx, &mut visit)?;
+    visit.result
+}
+
+struct Visit<'a, V: ?Sized> {
+    visitor: &'a mut V,
+    result: Result<(), CommandError>,
+}
+
+impl<V: FrameVisitor + ?Sized> Visit<'_, V> {
+    /// Calls the visitor unless it has already failed
+    fn call(&mut self, f: impl FnOnce(&mut V) -> Result<(), CommandError>) {
+        if self.result.is_ok() {
+            self.result = f(self.visitor);
+        }
+    }
+}
+
+fn visit_frame<V: FrameVisitor + ?Sized>(
+    buf: &mut Cursor<&[u8]>,
+    ctx: &ParseContext,
+    visit: &mut Visit<'_, V>,
+) -> Result<(), ParseFrameError> {
+    let offset = buf.position() as usize;
+    let line = get_line(buf)?;
+    let (frame_type, line) = match line.split_first() {
+        Some((frame_type, line)) => (*frame_type, line),
+        None => return Err(invalid_format("frame", offset)),
+    };
+    match frame_type {
+        STRING_IDENT if line == b"?" => {
+            let mut data = BytesMut::new();
+            read_chunks(buf, ctx, |chunk| data.extend_from_slice(chunk))?;
+            visit.call(|visitor| visitor.visit_string(&data));
+        }
+        STRING_IDENT | ERROR_IDENT => {
+            let context = if frame_type == STRING_IDENT {
+                "string length"
+            } else {
+                "error length"
+            };
+            let len = check_blob(buf, line, offset, ctx, context)?;
+            let data = &buf.chunk()[..len];
+            if frame_type == STRING_IDENT {
+                visit.call(|visitor| visitor.visit_string(data));
+            } else {
+                visit.call(|visitor| visitor.visit_error(data));
+            }
+            skip(buf, len + 2)?;
+        }
+        ARRAY_IDENT => {
+            let len = atoi::<usize>(line).ok_or_else(|| invalid_format("array length", offset))?;
+            visit.call(|visitor| visitor.start_array(len));
+            for _ in 0..len {
+                visit_frame(buf, ctx, visit)?;
+            }
+            visit.call(|visitor| visitor.end_array());
+        }
+        MAP_IDENT => {
+            let len = atoi::<usize>(line).ok_or_else(|| invalid_format("map length", offset))?;
+            visit.call(|visitor| visitor.start_map(len));
+            for _ in 0..len.saturating_mul(2) {
+                visit_frame(buf, ctx, visit)?;
+            }
+            visit.call(|visitor| visitor.end_map());
+        }
+        ATTRIBUTE_IDENT => {
+            buf.set_position(offset as u64);
+            skip_frame(buf, ctx)?;
+        }
+        _ => {
+            buf.set_position(offset as u64);
+            match parse_frame(buf, ctx)? {
+                Frame::Integer(val) => visit.call(|visitor| visitor.visit_integer(val)),
+                Frame::Boolean(val) => visit.call(|visitor| visitor.visit_boolean(val)),
+                Frame::Null => visit.call(|visitor| visitor.visit_null()),
+                Frame::Double(val) => visit.call(|visitor| visitor.visit_double(val)),
+                _ => unreachable!("only scalar frames are parsed"),
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Advances past a frame without building it. Frames are validated the same way as when they are parsed,
+/// scalars are simply parsed since that never allocates
+fn skip_frame(buf: &mut Cursor<&[u8]>, ctx: &ParseContext) -> Result<(), ParseFrameError> {
+    let offset = buf.position() as usize;
+    let line = get_line(buf)?;
+    let (frame_type, line) = match line.split_first() {
+        Some((frame_type, line)) => (*frame_type, line),
+        None => return Err(invalid_format("frame", offset)),
+    };
+    match frame_type {
+        STRING_IDENT if line == b"?" => read_chunks(buf, ctx, |_| {}),
+        STRING_IDENT => skip_blob(buf, line, offset, ctx, "string length"),
+        ERROR_IDENT => skip_blob(buf, line, offset, ctx, "error length"),
+        ARRAY_IDENT => skip_collection(buf, line, offset, ctx, "array length", 1),
+        MAP_IDENT => skip_collection(buf, line, offset, ctx, "map length", 2),
+        ATTRIBUTE_IDENT => skip_collection(buf, line, offset, ctx, "attribute length", 2),
+        _ => {
+            buf.set_position(offset as u64);
+            parse_frame(buf, ctx).map(drop)
+        }
+    }
+}
+
+fn skip_blob(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+    context: &'static str,
+) -> Result<(), ParseFrameError> {
+    let len = check_blob(buf, line, offset, ctx, context)?;
+    skip(buf, len + 2)
+}
+
+fn skip_collection(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+    context: &'static str,
+    frames_per_entry: usize,
+) -> Result<(), ParseFrameError> {
+    let len = atoi::<usize>(line).ok_or_else(|| invalid_format(context, offset))?;
+    let frames = len.saturating_mul(frames_per_entry);
+    ctx.take_elements(frames)?;
+    for _ in 0..frames {
+        skip_frame(buf, ctx)?;
+    }
+    Ok(())
+}
+
+fn parse_boolean(line: &[u8], offset: usize) -> Result<Frame, ParseFrameError> {
+    if line.len() != 1 {
+        return Err(invalid_format("boolean", offset));
     }
 
     let val = line[0];
 
+    // Some server dialects send t/f instead of 1/0, both are accepted while writes always use 1/0
     match val {
-        b'0' => Ok(Frame::Boolean(false)),
-        b'1' => Ok(Frame::Boolean(true)),
-        _ => Err(ParseFrameError::InvalidFormat),
+        b'0' | b'f' | b'F' => Ok(Frame::Boolean(false)),
+        b'1' | b't' | b'T' => Ok(Frame::Boolean(true)),
+        _ => Err(invalid_format("boolean", offset)),
     }
 }
 
-fn parse_null(line: &[u8]) -> Result<Frame, ParseFrameError> {
+fn parse_null(line: &[u8], offset: usize) -> Result<Frame, ParseFrameError> {
     if !line.is_empty() {
-        return Err(ParseFrameError::InvalidFormat);
+        return Err(invalid_format("null", offset));
     }
     Ok(Frame::Null)
 }
 
-fn parse_map(buf: &mut Cursor<&[u8]>, line: &[u8]) -> Result<Frame, ParseFrameError> {
-    let len = atoi::<usize>(line).ok_or(ParseFrameError::InvalidFormat)?;
-    let mut map = Vec::with_capacity(2 * len);
+fn parse_map(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+) -> Result<Frame, ParseFrameError> {
+    Ok(Frame::Map(parse_pairs(
+        buf,
+        line,
+        offset,
+        ctx,
+        "map length",
+    )?))
+}
+
+fn parse_pairs(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+    context: &'static str,
+) -> Result<Vec<Frame>, ParseFrameError> {
+    let len = atoi::<usize>(line).ok_or_else(|| invalid_format(context, offset))?;
+    ctx.take_elements(len.saturating_mul(2))?;
+    let mut map = Vec::with_capacity(len.min(buf.remaining()).saturating_mul(2));
     for _ in 0..len {
-        let key = parse(buf)?;
-        let value = parse(buf)?;
+        let key = parse_frame(buf, ctx)?;
+        let value = parse_frame(buf, ctx)?;
         map.push(key);
         map.push(value);
     }
 
-    Ok(Frame::Map(map))
+    Ok(map)
 }
 
-fn parse_double(line: &[u8]) -> Result<Frame, ParseFrameError> {
+fn parse_double(line: &[u8], offset: usize) -> Result<Frame, ParseFrameError> {
     let double = str::from_utf8(line)
-        .map_err(|_| ParseFrameError::InvalidFormat)?
+        .map_err(|_| invalid_format("double", offset))?
         .parse::<f64>()
-        .map_err(|_| ParseFrameError::InvalidFormat)?;
+        .map_err(|_| invalid_format("double", offset))?;
     Ok(Frame::Double(double))
 }
 
-fn parse_error(buf: &mut Cursor<&[u8]>, line: &[u8]) -> Result<Frame, ParseFrameError> {
-    let len = atoi::<usize>(line).ok_or(ParseFrameError::InvalidFormat)?;
-    let n = len + 2;
+fn parse_error(
+    buf: &mut Cursor<&[u8]>,
+    line: &[u8],
+    offset: usize,
+    ctx: &ParseContext,
+) -> Result<Frame, ParseFrameError> {
+    Ok(Frame::Error(parse_blob(
+        buf,
+        line,
+        offset,
+        ctx,
+        "error length",
+    )?))
+}
 
-    if buf.remaining() < n {
-        return Err(ParseFrameError::Incomplete);
+/// Serializes the frame into the buffer in the format read by `parse`, nested frames are serialized recursively
+pub(crate) fn serialize(frame: &Frame, dst: &mut BytesMut) {
+    match frame {
+        Frame::String(data) => serialize_blob(STRING_IDENT, data, dst),
+        Frame::Integer(val) => serialize_line(INTEGER_IDENT, val, dst),
+        Frame::Array(array) => serialize_collection(ARRAY_IDENT, array.len(), array, dst),
+        Frame::Boolean(val) => serialize_line(BOOLEAN_IDENT, u8::from(*val), dst),
+        Frame::Null => serialize_line(NULL_IDENT, "", dst),
+        Frame::Map(map) => serialize_collection(MAP_IDENT, map.len() / 2, map, dst),
+        Frame::Double(val) => serialize_line(DOUBLE_IDENT, val, dst),
+        Frame::Error(data) => serialize_blob(ERROR_IDENT, data, dst),
+        Frame::Attribute(map) => serialize_collection(ATTRIBUTE_IDENT, map.len() / 2, map, dst),
     }
+}
 
-    let data = Bytes::copy_from_slice(&buf.chunk()[..len]);
+fn serialize_line(ident: u8, val: impl std::fmt::Display, dst: &mut BytesMut) {
+    dst.put_u8(ident);
+    dst.put_slice(format!("{}\r\n", val).as_bytes());
+}
 
-    skip(buf, n)?;
+/// Serializes the args as a command encoded in the dialect, i.e. an array frame holding them. The bytes are what a
+/// connection using the dialect writes for a command with these args, so they can be built without a connection.
+/// The typed dialect does not clone the args into a `Frame::Array`.
+/// Fails with `FrameError::UnpairedMapEntry` if an arg holds a map with an odd number of elements, like a connection would
+pub fn encode_command(args: &[Frame], dialect: Dialect) -> Result<Bytes, FrameError> {
+    if args.iter().any(has_unpaired_entries) {
+        return Err(FrameError::UnpairedMapEntry);
+    }
+    if dialect != Dialect::Typed {
+        let cmd = Frame::Array(args.to_vec());
+        let cmd = command::encode(&cmd, dialect);
+        let mut buf = BytesMut::with_capacity(cmd.serialized_len());
+        serialize(&cmd, &mut buf);
+        return Ok(buf.freeze());
+    }
+    let mut buf = BytesMut::with_capacity(
+        line_len(args.len()) + args.iter().map(Frame::serialized_len).sum::<usize>(),
+    );
+    serialize_collection(ARRAY_IDENT, args.len(), args, &mut buf);
+    Ok(buf.freeze())
+}
+
+/// Writes the frame for `Frame::truncate_for_log`, returning false once the output reached the limit
+fn write_for_log(frame: &Frame, out: &mut String, limit: usize) -> bool {
+    let scalar = match frame {
+        Frame::String(data) => return write_bytes_for_log(data, out, limit),
+        Frame::Error(data) => {
+            out.push_str("(error) ");
+            return write_bytes_for_log(data, out, limit);
+        }
+        Frame::Array(children) => return write_children_for_log(children, 1, "[", "]", out, limit),
+        Frame::Map(children) => return write_children_for_log(children, 2, "{", "}", out, limit),
+        Frame::Attribute(children) => {
+            return write_children_for_log(children, 2, "|{", "}", out, limit)
+        }
+        Frame::Integer(val) => val.to_string(),
+        Frame::Double(val) => val.to_string(),
+        Frame::Boolean(val) => val.to_string(),
+        Frame::Null => "null".to_string(),
+    };
+    out.push_str(&scalar);
+    out.len() < limit
+}
+
+fn write_bytes_for_log(data: &[u8], out: &mut String, limit: usize) -> bool {
+    let text = String::from_utf8_lossy(data).escape_debug().to_string();
+    let remaining = limit.saturating_sub(out.len() + 2);
+    if text.len() <= remaining {
+        out.push('"');
+        out.push_str(&text);
+        out.push('"');
+        return out.len() < limit;
+    }
+
+    let mut end = remaining;
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    out.push('"');
+    out.push_str(&text[..end]);
+    out.push_str(&format!("...\" ({} bytes)", data.len()));
+    false
+}
+
+fn write_children_for_log(
+    children: &[Frame],
+    frames_per_entry: usize,
+    open: &str,
+    close: &str,
+    out: &mut String,
+    limit: usize,
+) -> bool {
+    let entries = children.len().div_ceil(frames_per_entry);
+    let mut complete = true;
+    out.push_str(open);
+    for (i, entry) in children.chunks(frames_per_entry).enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        if out.len() >= limit {
+            out.push_str(&format!("... ({} more)", entries - i));
+            complete = false;
+            break;
+        }
+
+        complete = write_for_log(&entry[0], out, limit);
+        if let (true, Some(value)) = (complete, entry.get(1)) {
+            out.push_str(": ");
+            complete = write_for_log(value, out, limit);
+        }
+        if !complete {
+            if entries - i > 1 {
+                out.push_str(&format!(", ... ({} more)", entries - i - 1));
+            }
+            break;
+        }
+    }
+    out.push_str(close);
+    complete && out.len() < limit
+}
 
-    Ok(Frame::Error(data))
+/// Returns true if the frame or any nested frame is a map or attribute with an odd number of elements,
+/// which can not be serialized since the header holds the number of pairs
+pub(crate) fn has_unpaired_entries(frame: &Frame) -> bool {
+    match frame {
+        Frame::Map(children) | Frame::Attribute(children) if children.len() % 2 != 0 => true,
+        Frame::Array(children) | Frame::Map(children) | Frame::Attribute(children) => {
+            children.iter().any(has_unpaired_entries)
+        }
+        _ => false,
+    }
+}
+
+/// Returns the length of a line written by `serialize_line`, counting the formatted value without allocating
+fn line_len(val: impl std::fmt::Display) -> usize {
+    struct Counter(usize);
+
+    impl std::fmt::Write for Counter {
+        fn write_str(&mut self, s: &str) -> std::fmt::Result {
+            self.0 += s.len();
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    let _ = std::fmt::Write::write_fmt(&mut counter, format_args!("{}", val));
+    counter.0 + 3
+}
+
+fn serialize_blob(ident: u8, data: &[u8], dst: &mut BytesMut) {
+    serialize_line(ident, data.len(), dst);
+    dst.put_slice(data);
+    dst.put_slice(b"\r\n");
+}
+
+fn serialize_collection(ident: u8, len: usize, frames: &[Frame], dst: &mut BytesMut) {
+    serialize_line(ident, len, dst);
+    for frame in frames {
+        serialize(frame, dst);
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use bytes::{BufMut, BytesMut};
+    use bytes::BufMut;
+    use std::collections::HashSet;
     use std::fs;
     use std::io::Cursor;
     use std::path::{Path, PathBuf};
@@ -234,28 +1186,255 @@ mod tests {
         frame.copy_to_bytes(frame.len())
     }
 
+    #[test]
+    fn serialized_len_given_each_kind_matches_serialized_bytes() {
+        let frames = vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Error(Bytes::new()),
+            Frame::Integer(-1234),
+            Frame::Boolean(true),
+            Frame::Null,
+            Frame::Double(1.5),
+            Frame::Array(vec![Frame::Integer(1); 12]),
+            Frame::Map(vec![Frame::String(Bytes::from("key")), Frame::Null]),
+            Frame::Attribute(vec![Frame::Integer(1), Frame::Double(f64::NAN)]),
+        ];
+        for frame in frames {
+            let mut buf = BytesMut::new();
+            serialize(&frame, &mut buf);
+            assert_eq!(frame.serialized_len(), buf.len(), "{:?}", frame)
+        }
+    }
+
+    #[test]
+    fn has_unpaired_entries_given_nested_odd_map_returns_true() {
+        let even = Frame::Map(vec![Frame::Integer(1), Frame::Null]);
+        let odd = Frame::Map(vec![Frame::Integer(1)]);
+        assert!(!has_unpaired_entries(&Frame::Array(vec![even.clone()])));
+        assert!(has_unpaired_entries(&Frame::Array(vec![even, odd])));
+        assert!(has_unpaired_entries(&Frame::Attribute(vec![Frame::Null])))
+    }
+
+    #[test]
+    fn encode_command_given_odd_length_map_returns_unpaired_map_entry() {
+        let args = [
+            Frame::String(Bytes::from("SET")),
+            Frame::Map(vec![Frame::Integer(1)]),
+        ];
+        assert_eq!(
+            encode_command(&args, Dialect::Typed),
+            Err(FrameError::UnpairedMapEntry)
+        )
+    }
+
+    #[test]
+    fn validate_given_odd_length_map_returns_unpaired_map_entry() {
+        let frame = Frame::Array(vec![Frame::Map(vec![Frame::Integer(1)])]);
+        assert_eq!(
+            frame.validate(&FrameLimits::new()),
+            Err(FrameError::UnpairedMapEntry)
+        )
+    }
+
+    #[test]
+    fn validate_given_oversized_array_returns_error() {
+        let frame = Frame::Array(vec![Frame::Array(vec![Frame::Integer(1); 3]); 2]);
+        let mut limits = FrameLimits::new();
+        limits.set_max_elements(8);
+        assert_eq!(frame.validate(&limits), Ok(()));
+        limits.set_max_elements(7);
+        assert_eq!(frame.validate(&limits), Err(FrameError::TooManyElements));
+
+        let mut limits = FrameLimits::new();
+        limits.set_max_frame_size(frame.serialized_len() - 1);
+        assert_eq!(frame.validate(&limits), Err(FrameError::FrameTooLarge))
+    }
+
+    #[test]
+    fn truncate_for_log_given_small_frame_renders_it_whole() {
+        let frame = Frame::Array(vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Map(vec![Frame::Integer(1), Frame::Null]),
+            Frame::Error(Bytes::from("ERR")),
+        ]);
+        assert_eq!(
+            frame.truncate_for_log(64),
+            r#"["foo", {1: null}, (error) "ERR"]"#
+        )
+    }
+
+    #[test]
+    fn truncate_for_log_given_long_string_truncates_with_length() {
+        let frame = Frame::String(Bytes::from(vec![b'a'; 1000]));
+        assert_eq!(
+            frame.truncate_for_log(12),
+            r#""aaaaaaaaaa..." (1000 bytes)"#
+        )
+    }
+
+    #[test]
+    fn truncate_for_log_given_big_array_truncates_with_remaining_count() {
+        let frame = Frame::Array((0..1000).map(Frame::Integer).collect());
+        assert_eq!(frame.truncate_for_log(10), "[0, 1, 2, ... (997 more)]")
+    }
+
+    #[test]
+    fn len_given_string_returns_byte_length() {
+        assert_eq!(Frame::String(Bytes::from("foo")).len(), Some(3))
+    }
+
+    #[test]
+    fn len_given_error_returns_byte_length() {
+        assert_eq!(Frame::Error(Bytes::from("fo")).len(), Some(2))
+    }
+
+    #[test]
+    fn len_given_array_returns_element_count() {
+        let frame = Frame::Array(vec![Frame::Integer(1), Frame::Null]);
+        assert_eq!(frame.len(), Some(2))
+    }
+
+    #[test]
+    fn len_given_map_returns_pair_count() {
+        let frame = Frame::Map(vec![
+            Frame::String(Bytes::from("a")),
+            Frame::Integer(1),
+            Frame::String(Bytes::from("b")),
+            Frame::Integer(2),
+        ]);
+        assert_eq!(frame.len(), Some(2))
+    }
+
+    #[test]
+    fn len_given_scalar_returns_none() {
+        assert_eq!(Frame::Integer(1).len(), None);
+        assert_eq!(Frame::Double(1.0).len(), None);
+        assert_eq!(Frame::Boolean(true).len(), None);
+        assert_eq!(Frame::Null.len(), None);
+    }
+
+    #[test]
+    fn is_empty_given_empty_and_non_empty_frames_returns_accordingly() {
+        assert_eq!(Frame::Array(Vec::new()).is_empty(), Some(true));
+        assert_eq!(Frame::String(Bytes::from("foo")).is_empty(), Some(false));
+        assert_eq!(Frame::Null.is_empty(), None);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn to_json_given_nested_array_and_map_returns_json() {
+        let frame = Frame::Array(vec![
+            Frame::Integer(1),
+            Frame::Map(vec![
+                Frame::String(Bytes::from("foo")),
+                Frame::Array(vec![Frame::Boolean(true), Frame::Null]),
+                Frame::Integer(2),
+                Frame::Double(1.5),
+            ]),
+            Frame::String(Bytes::from_static(&[0xff, 0xfe])),
+            Frame::Error(Bytes::from("bar")),
+        ]);
+        assert_eq!(
+            frame.to_json(),
+            serde_json::json!([
+                1,
+                {"foo": [true, null], "2": 1.5},
+                "//4=",
+                {"error": "bar"}
+            ])
+        )
+    }
+
+    #[test]
+    fn iter_given_nested_array_yields_children() {
+        let frame = Frame::Array(vec![
+            Frame::Integer(1),
+            Frame::Array(vec![Frame::Integer(2), Frame::Integer(3)]),
+            Frame::Null,
+        ]);
+        assert_eq!(frame.iter().count(), 3);
+        assert_eq!(
+            frame
+                .iter()
+                .map(|child| child.iter().count())
+                .sum::<usize>(),
+            2
+        )
+    }
+
+    #[test]
+    fn iter_given_scalar_yields_nothing() {
+        assert_eq!(Frame::Integer(1).iter().count(), 0)
+    }
+
+    #[test]
+    fn error_message_and_code_given_error_return_bytes() {
+        let frame = Frame::Error(Bytes::from("WRONGTYPE not a string"));
+        assert_eq!(frame.error_message(), Some(&b"WRONGTYPE not a string"[..]));
+        assert_eq!(frame.error_code(), Some(&b"WRONGTYPE"[..]))
+    }
+
+    #[test]
+    fn error_message_and_code_given_non_error_return_none() {
+        let frame = Frame::String(Bytes::from("foo"));
+        assert_eq!(frame.error_message(), None);
+        assert_eq!(frame.error_code(), None)
+    }
+
+    #[test]
+    fn map_entry_given_integer_key_returns_value() {
+        let frame = Frame::Map(vec![
+            Frame::Integer(1),
+            Frame::String(Bytes::from("foo")),
+            Frame::Integer(2),
+            Frame::String(Bytes::from("bar")),
+        ]);
+        assert_eq!(
+            frame.map_entry(&2i64),
+            Some(&Frame::String(Bytes::from("bar")))
+        )
+    }
+
+    #[test]
+    fn map_entry_given_undecodable_keys_skips_them() {
+        let frame = Frame::Map(vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Integer(1),
+            Frame::Integer(2),
+            Frame::Integer(3),
+        ]);
+        assert_eq!(frame.map_entry(&2i64), Some(&Frame::Integer(3)))
+    }
+
+    #[test]
+    fn map_entry_given_missing_key_or_non_map_returns_none() {
+        let frame = Frame::Map(vec![Frame::Integer(1), Frame::Integer(2)]);
+        assert_eq!(frame.map_entry(&3i64), None);
+        assert_eq!(Frame::Integer(1).map_entry(&1i64), None)
+    }
+
     #[test]
     fn parse_given_empty_line_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("frame", 0)))
     }
 
     #[test]
     fn parse_given_unknown_type_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"foo\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("frame type", 0)))
     }
 
     #[test]
     fn parse_given_string_with_no_length_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"$\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("string length", 0)))
     }
 
     #[test]
     fn parse_given_string_with_invalid_length_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"$abc\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("string length", 0)))
     }
 
     #[test]
@@ -277,9 +1456,52 @@ mod tests {
     }
 
     #[test]
-    fn parse_given_string_with_length_less_than_length_of_data_returns_data_upto_given_length() {
+    fn parse_given_string_with_zero_length_and_partial_delimiter_returns_incomplete_error() {
+        let mut buf = get_cursor_from_bytes(b"$0\r\n\r");
+        assert_eq!(parse(&mut buf), Err(ParseFrameError::Incomplete))
+    }
+
+    #[test]
+    fn parse_given_empty_string_followed_by_frame_returns_both_frames() {
+        let mut buf = get_cursor_from_bytes(b"$0\r\n\r\n%1\r\n");
+        assert_eq!(parse(&mut buf), Ok(Frame::String(Bytes::new())));
+        assert_eq!(parse(&mut buf), Ok(Frame::Integer(1)));
+        assert!(!buf.has_remaining())
+    }
+
+    #[test]
+    fn parse_given_empty_error_followed_by_frame_returns_both_frames() {
+        let mut buf = get_cursor_from_bytes(b"!0\r\n\r\n%1\r\n");
+        assert_eq!(parse(&mut buf), Ok(Frame::Error(Bytes::new())));
+        assert_eq!(parse(&mut buf), Ok(Frame::Integer(1)));
+        assert!(!buf.has_remaining())
+    }
+
+    #[test]
+    fn parse_given_empty_array_and_map_followed_by_frame_returns_all_frames() {
+        let mut buf = get_cursor_from_bytes(b"*0\r\n#0\r\n%1\r\n");
+        assert_eq!(parse(&mut buf), Ok(Frame::Array(Vec::new())));
+        assert_eq!(parse(&mut buf), Ok(Frame::Map(Vec::new())));
+        assert_eq!(parse(&mut buf), Ok(Frame::Integer(1)));
+        assert!(!buf.has_remaining())
+    }
+
+    #[test]
+    fn parse_given_array_of_empty_strings_returns_empty_strings() {
+        let mut buf = get_cursor_from_bytes(b"*2\r\n$0\r\n\r\n$0\r\n\r\n");
+        assert_eq!(
+            parse(&mut buf),
+            Ok(Frame::Array(vec![
+                Frame::String(Bytes::new()),
+                Frame::String(Bytes::new())
+            ]))
+        )
+    }
+
+    #[test]
+    fn parse_given_string_with_length_less_than_length_of_data_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"$1\r\nfoo\r\n");
-        assert_eq!(parse(&mut buf), Ok(Frame::String(Bytes::from("f"))))
+        assert_eq!(parse(&mut buf), Err(invalid_format("delimiter", 0)))
     }
 
     #[test]
@@ -332,16 +1554,80 @@ mod tests {
         assert_eq!(parse(&mut buf), Ok(Frame::String(Bytes::from(file_data))))
     }
 
+    fn assert_payload_round_trips(ident: u8, payload: &[u8]) {
+        let mut data = get_frame_from_file(payload, ident).to_vec();
+        data.extend_from_slice(b"%1\r\n");
+        let mut buf = get_cursor_from_bytes(&data);
+        let expected = match ident {
+            STRING_IDENT => Frame::String(Bytes::copy_from_slice(payload)),
+            _ => Frame::Error(Bytes::copy_from_slice(payload)),
+        };
+        assert_eq!(parse(&mut buf), Ok(expected));
+        assert_eq!(parse(&mut buf), Ok(Frame::Integer(1)));
+        assert!(!buf.has_remaining())
+    }
+
+    #[test]
+    fn parse_given_string_with_embedded_crlf_does_not_desync_next_frame() {
+        assert_payload_round_trips(STRING_IDENT, b"\r\n%2\r\n\r\n");
+    }
+
+    #[test]
+    fn parse_given_string_with_lone_cr_does_not_desync_next_frame() {
+        assert_payload_round_trips(STRING_IDENT, b"foo\rbar\r");
+    }
+
+    #[test]
+    fn parse_given_string_with_lone_lf_does_not_desync_next_frame() {
+        assert_payload_round_trips(STRING_IDENT, b"\nfoo\nbar");
+    }
+
+    #[test]
+    fn parse_given_string_ending_with_cr_before_delimiter_does_not_desync_next_frame() {
+        assert_payload_round_trips(STRING_IDENT, b"foo\r");
+    }
+
+    #[test]
+    fn parse_given_error_with_embedded_crlf_does_not_desync_next_frame() {
+        assert_payload_round_trips(ERROR_IDENT, b"ERR\r\n$3\r\nfoo\r\n");
+    }
+
+    #[test]
+    fn parse_given_error_with_lone_cr_and_lf_does_not_desync_next_frame() {
+        assert_payload_round_trips(ERROR_IDENT, b"\rERR\nfoo\r");
+    }
+
+    #[test]
+    fn parse_given_array_of_strings_with_embedded_delimiters_returns_strings() {
+        let mut buf = get_cursor_from_bytes(b"*3\r\n$2\r\n\r\n\r\n$1\r\n\r\r\n$1\r\n\n\r\n%1\r\n");
+        assert_eq!(
+            parse(&mut buf),
+            Ok(Frame::Array(vec![
+                Frame::String(Bytes::from("\r\n")),
+                Frame::String(Bytes::from("\r")),
+                Frame::String(Bytes::from("\n")),
+            ]))
+        );
+        assert_eq!(parse(&mut buf), Ok(Frame::Integer(1)))
+    }
+
+    #[test]
+    fn parse_given_string_with_length_greater_than_payload_before_crlf_returns_invalid_format_error(
+    ) {
+        let mut buf = get_cursor_from_bytes(b"$4\r\nfoo\r\n%1\r\n");
+        assert_eq!(parse(&mut buf), Err(invalid_format("delimiter", 0)))
+    }
+
     #[test]
     fn parse_given_invalid_integer_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"%abc\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("integer", 0)))
     }
 
     #[test]
     fn parse_given_empty_integer_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"%\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("integer", 0)))
     }
 
     #[test]
@@ -365,7 +1651,7 @@ mod tests {
     #[test]
     fn parse_given_out_of_range_integer_returns_format_error() {
         let mut buf = get_cursor_from_bytes(b"%9223372036854775808\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("integer", 0)))
     }
 
     #[test]
@@ -380,10 +1666,40 @@ mod tests {
         assert_eq!(parse(&mut buf), Ok(Frame::Boolean(true)))
     }
 
+    #[test]
+    fn parse_given_t_returns_true() {
+        let mut buf = get_cursor_from_bytes(b"^t\r\n");
+        assert_eq!(parse(&mut buf), Ok(Frame::Boolean(true)))
+    }
+
+    #[test]
+    fn parse_given_uppercase_t_returns_true() {
+        let mut buf = get_cursor_from_bytes(b"^T\r\n");
+        assert_eq!(parse(&mut buf), Ok(Frame::Boolean(true)))
+    }
+
+    #[test]
+    fn parse_given_f_returns_false() {
+        let mut buf = get_cursor_from_bytes(b"^f\r\n");
+        assert_eq!(parse(&mut buf), Ok(Frame::Boolean(false)))
+    }
+
+    #[test]
+    fn parse_given_uppercase_f_returns_false() {
+        let mut buf = get_cursor_from_bytes(b"^F\r\n");
+        assert_eq!(parse(&mut buf), Ok(Frame::Boolean(false)))
+    }
+
+    #[test]
+    fn parse_given_invalid_boolean_byte_returns_invalid_format_error() {
+        let mut buf = get_cursor_from_bytes(b"^x\r\n");
+        assert_eq!(parse(&mut buf), Err(invalid_format("boolean", 0)))
+    }
+
     #[test]
     fn parse_given_invalid_boolean_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"^foo\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("boolean", 0)))
     }
 
     #[test]
@@ -395,19 +1711,19 @@ mod tests {
     #[test]
     fn parse_given_invalid_null_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"-foo\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("null", 0)))
     }
 
     #[test]
     fn parse_given_double_with_invalid_decimal_part_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b".20.foo\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("double", 0)))
     }
 
     #[test]
     fn parse_given_double_with_invalid_integer_part_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b".foo.90\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("double", 0)))
     }
 
     #[test]
@@ -431,19 +1747,19 @@ mod tests {
     #[test]
     fn parse_given_invalid_double_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b".abc\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("double", 0)))
     }
 
     #[test]
     fn parse_given_error_with_no_length_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"!\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("error length", 0)))
     }
 
     #[test]
     fn parse_given_error_with_invalid_length_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"!abc\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("error length", 0)))
     }
 
     #[test]
@@ -465,9 +1781,9 @@ mod tests {
     }
 
     #[test]
-    fn parse_given_error_with_length_less_than_length_of_data_returns_data_upto_given_length() {
+    fn parse_given_error_with_length_less_than_length_of_data_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"!1\r\nfoo\r\n");
-        assert_eq!(parse(&mut buf), Ok(Frame::Error(Bytes::from("f"))))
+        assert_eq!(parse(&mut buf), Err(invalid_format("delimiter", 0)))
     }
 
     #[test]
@@ -523,7 +1839,7 @@ mod tests {
     #[test]
     fn parse_given_array_with_no_length_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"*\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("array length", 0)))
     }
 
     #[test]
@@ -535,13 +1851,13 @@ mod tests {
     #[test]
     fn parse_given_array_with_invalid_length_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"*abc\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("array length", 0)))
     }
 
     #[test]
     fn parse_given_map_with_no_length_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"#\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("map length", 0)))
     }
 
     #[test]
@@ -553,7 +1869,160 @@ mod tests {
     #[test]
     fn parse_given_map_with_invalid_length_returns_invalid_format_error() {
         let mut buf = get_cursor_from_bytes(b"#abc\r\n");
-        assert_eq!(parse(&mut buf), Err(ParseFrameError::InvalidFormat))
+        assert_eq!(parse(&mut buf), Err(invalid_format("map length", 0)))
+    }
+
+    #[test]
+    fn parse_given_array_with_invalid_integer_returns_offset_of_integer() {
+        let mut buf = get_cursor_from_bytes(b"*2\r\n%1\r\n%abc\r\n");
+        assert_eq!(parse(&mut buf), Err(invalid_format("integer", 8)))
+    }
+
+    #[test]
+    fn invalid_format_error_message_contains_context_and_offset() {
+        let mut buf = get_cursor_from_bytes(b"*2\r\n%1\r\n%abc\r\n");
+        assert_eq!(
+            parse(&mut buf).unwrap_err().to_string(),
+            "invalid integer at offset 8"
+        )
+    }
+
+    #[test]
+    fn parse_given_string_with_max_length_returns_invalid_format_error() {
+        let mut buf = get_cursor_from_bytes(b"$18446744073709551615\r\nfoo\r\n");
+        assert_eq!(parse(&mut buf), Err(invalid_format("string length", 0)))
+    }
+
+    #[cfg(target_pointer_width = "32")]
+    #[test]
+    fn parse_given_array_with_length_beyond_32_bit_range_returns_invalid_format_error() {
+        let mut buf = get_cursor_from_bytes(b"*4294967296\r\n%1\r\n");
+        assert_eq!(parse(&mut buf), Err(invalid_format("array length", 0)))
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn parse_given_array_with_length_beyond_32_bit_range_returns_incomplete_error() {
+        let mut buf = get_cursor_from_bytes(b"*4294967296\r\n%1\r\n");
+        assert_eq!(parse(&mut buf), Err(ParseFrameError::Incomplete))
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    fn parse_given_map_with_length_beyond_32_bit_range_returns_incomplete_error() {
+        let mut buf = get_cursor_from_bytes(b"#4294967296\r\n%1\r\n%2\r\n");
+        assert_eq!(parse(&mut buf), Err(ParseFrameError::Incomplete))
+    }
+
+    #[test]
+    fn parse_given_attribute_returns_attribute() {
+        let mut buf = get_cursor_from_bytes(b"|1\r\n$3\r\nttl\r\n%10\r\n");
+        assert_eq!(
+            parse(&mut buf),
+            Ok(Frame::Attribute(vec![
+                Frame::String(Bytes::from("ttl")),
+                Frame::Integer(10)
+            ]))
+        )
+    }
+
+    #[test]
+    fn parse_given_attribute_with_invalid_length_returns_invalid_format_error() {
+        let mut buf = get_cursor_from_bytes(b"|abc\r\n");
+        assert_eq!(parse(&mut buf), Err(invalid_format("attribute length", 0)))
+    }
+
+    #[test]
+    fn parse_given_streamed_string_returns_string() {
+        let mut buf = get_cursor_from_bytes(b"$?\r\n;3\r\nfoo\r\n;5\r\nbar\r\n\r\n;0\r\n");
+        assert_eq!(
+            parse(&mut buf),
+            Ok(Frame::String(Bytes::from("foobar\r\n")))
+        )
+    }
+
+    #[test]
+    fn parse_given_streamed_string_without_terminator_returns_incomplete_error() {
+        let mut buf = get_cursor_from_bytes(b"$?\r\n;3\r\nfoo\r\n;3\r\nbar\r\n");
+        assert_eq!(parse(&mut buf), Err(ParseFrameError::Incomplete))
+    }
+
+    #[test]
+    fn parse_given_streamed_string_with_invalid_chunk_returns_invalid_format_error() {
+        let mut buf = get_cursor_from_bytes(b"$?\r\n%3\r\n");
+        assert_eq!(parse(&mut buf), Err(invalid_format("string chunk", 4)))
+    }
+
+    #[test]
+    fn parse_with_limits_given_streamed_string_exceeding_max_frame_size_returns_frame_too_large_error(
+    ) {
+        let mut limits = FrameLimits::new();
+        limits.set_max_frame_size(16);
+        let mut buf = get_cursor_from_bytes(b"$?\r\n;3\r\nfoo\r\n;3\r\nbar\r\n;0\r\n");
+        assert_eq!(
+            parse_with_limits(&mut buf, &limits),
+            Err(ParseFrameError::FrameTooLarge)
+        )
+    }
+
+    #[test]
+    fn parse_with_limits_given_string_length_exceeding_max_frame_size_returns_frame_too_large_error(
+    ) {
+        let mut limits = FrameLimits::new();
+        limits.set_max_frame_size(16);
+        let mut buf = get_cursor_from_bytes(b"$100\r\n");
+        assert_eq!(
+            parse_with_limits(&mut buf, &limits),
+            Err(ParseFrameError::FrameTooLarge)
+        )
+    }
+
+    #[test]
+    fn parse_with_limits_given_array_exceeding_max_elements_returns_frame_too_large_error() {
+        let mut limits = FrameLimits::new();
+        limits.set_max_elements(3);
+        let mut cursor = get_cursor_from_bytes(b"*4\r\n%1\r\n%2\r\n%3\r\n%4\r\n");
+        assert_eq!(
+            parse_with_limits(&mut cursor, &limits),
+            Err(ParseFrameError::FrameTooLarge)
+        )
+    }
+
+    #[test]
+    fn parse_with_limits_given_nested_collections_exceeding_max_elements_returns_frame_too_large_error(
+    ) {
+        let mut limits = FrameLimits::new();
+        limits.set_max_elements(4);
+        let mut cursor = get_cursor_from_bytes(b"*2\r\n*1\r\n%1\r\n#1\r\n%2\r\n%3\r\n");
+        assert_eq!(
+            parse_with_limits(&mut cursor, &limits),
+            Err(ParseFrameError::FrameTooLarge)
+        )
+    }
+
+    #[test]
+    fn parse_with_limits_given_array_within_max_elements_returns_frame() {
+        let mut limits = FrameLimits::new();
+        limits.set_max_elements(3);
+        let mut cursor = get_cursor_from_bytes(b"*2\r\n*1\r\n%1\r\n%2\r\n");
+        assert_eq!(
+            parse_with_limits(&mut cursor, &limits).unwrap(),
+            Frame::Array(vec![
+                Frame::Array(vec![Frame::Integer(1)]),
+                Frame::Integer(2)
+            ])
+        )
+    }
+
+    #[test]
+    fn parse_with_limits_given_frame_within_max_frame_size_returns_frame() {
+        let mut limits = FrameLimits::new();
+        limits.set_max_frame_size(9);
+        let mut buf = get_cursor_from_bytes(b"$3\r\nfoo\r\n");
+        assert_eq!(
+            parse_with_limits(&mut buf, &limits),
+            Ok(Frame::String(Bytes::from("foo")))
+        )
     }
 
     #[test]
@@ -561,4 +2030,258 @@ mod tests {
         let mut buf = get_cursor_from_bytes(b"#2\r\n$3\r\nfoo\r\n");
         assert_eq!(parse(&mut buf), Err(ParseFrameError::Incomplete))
     }
+
+    #[test]
+    fn serialize_given_nested_collections_writes_each_level() {
+        let frame = Frame::Array(vec![
+            Frame::Map(vec![
+                Frame::String(Bytes::from("foo")),
+                Frame::Array(vec![Frame::Null]),
+            ]),
+            Frame::Boolean(true),
+        ]);
+        let mut buf = BytesMut::new();
+        serialize(&frame, &mut buf);
+        assert_eq!(&buf[..], b"*2\r\n#1\r\n$3\r\nfoo\r\n*1\r\n-\r\n^1\r\n")
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn serialize_then_parse_returns_same_frame(frame in crate::strategies::frame()) {
+            proptest::prop_assert_eq!(crate::strategies::reparse(&frame), frame);
+        }
+    }
+
+    #[test]
+    fn logically_eq_given_integer_and_double_with_same_value_returns_true() {
+        assert!(Frame::Integer(1).logically_eq(&Frame::Double(1.0)));
+        assert!(Frame::Double(-3.0).logically_eq(&Frame::Integer(-3)));
+        assert_ne!(Frame::Integer(1), Frame::Double(1.0))
+    }
+
+    #[test]
+    fn logically_eq_given_integer_and_different_double_returns_false() {
+        assert!(!Frame::Integer(1).logically_eq(&Frame::Double(1.5)));
+        assert!(!Frame::Integer(i64::MAX).logically_eq(&Frame::Double(i64::MAX as f64)));
+        assert!(!Frame::Integer(0).logically_eq(&Frame::Double(f64::NAN)))
+    }
+
+    #[test]
+    fn logically_eq_given_maps_in_different_order_returns_true() {
+        let a = Frame::Map(vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Integer(1),
+            Frame::String(Bytes::from("bar")),
+            Frame::Array(vec![Frame::Double(2.0)]),
+        ]);
+        let b = Frame::Map(vec![
+            Frame::String(Bytes::from("bar")),
+            Frame::Array(vec![Frame::Integer(2)]),
+            Frame::String(Bytes::from("foo")),
+            Frame::Double(1.0),
+        ]);
+        assert!(a.logically_eq(&b));
+        assert_ne!(a, b)
+    }
+
+    #[test]
+    fn logically_eq_given_maps_with_different_entries_returns_false() {
+        let a = Frame::Map(vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Integer(1),
+            Frame::String(Bytes::from("foo")),
+            Frame::Integer(1),
+        ]);
+        let b = Frame::Map(vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Integer(1),
+            Frame::String(Bytes::from("bar")),
+            Frame::Integer(1),
+        ]);
+        assert!(!a.logically_eq(&b));
+        assert!(!b.logically_eq(&a))
+    }
+
+    #[test]
+    fn logically_eq_given_arrays_in_different_order_returns_false() {
+        let a = Frame::Array(vec![Frame::Integer(1), Frame::Integer(2)]);
+        let b = Frame::Array(vec![Frame::Integer(2), Frame::Integer(1)]);
+        assert!(!a.logically_eq(&b))
+    }
+
+    #[test]
+    fn parse_head_with_limits_given_array_returns_first_elements_and_skips_the_rest() {
+        let mut cursor = get_cursor_from_bytes(
+            b"*5\r\n$3\r\nfoo\r\n%1\r\n*2\r\n$?\r\n;2\r\nab\r\n;0\r\n.1.5\r\n#1\r\n!3\r\nerr\r\n-\r\n$3\r\nbar\r\n%2\r\n",
+        );
+        let frame = parse_head_with_limits(&mut cursor, &FrameLimits::default(), 2).unwrap();
+        assert_eq!(
+            frame,
+            Frame::Array(vec![Frame::String(Bytes::from("foo")), Frame::Integer(1)])
+        );
+        assert_eq!(parse(&mut cursor).unwrap(), Frame::Integer(2))
+    }
+
+    #[test]
+    fn parse_head_with_limits_given_incomplete_skipped_element_returns_incomplete_error() {
+        let mut cursor = get_cursor_from_bytes(b"*2\r\n%1\r\n$3\r\nfo");
+        assert_eq!(
+            parse_head_with_limits(&mut cursor, &FrameLimits::default(), 1),
+            Err(ParseFrameError::Incomplete)
+        )
+    }
+
+    #[test]
+    fn parse_head_with_limits_given_invalid_skipped_element_returns_invalid_format_error() {
+        let mut cursor = get_cursor_from_bytes(b"*2\r\n%1\r\n$3\r\nfoobar\r\n");
+        assert_eq!(
+            parse_head_with_limits(&mut cursor, &FrameLimits::default(), 1),
+            Err(invalid_format("delimiter", 8))
+        )
+    }
+
+    #[test]
+    fn parse_head_with_limits_given_non_array_returns_whole_frame() {
+        let mut cursor = get_cursor_from_bytes(b"#1\r\n%1\r\n%2\r\n");
+        assert_eq!(
+            parse_head_with_limits(&mut cursor, &FrameLimits::default(), 0).unwrap(),
+            Frame::Map(vec![Frame::Integer(1), Frame::Integer(2)])
+        )
+    }
+
+    #[test]
+    fn hash_set_given_equal_frames_dedups_them() {
+        let mut set = HashSet::new();
+        assert!(set.insert(Frame::Array(vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Double(1.5)
+        ])));
+        assert!(!set.insert(Frame::Array(vec![
+            Frame::String(Bytes::from("foo")),
+            Frame::Double(1.5)
+        ])));
+        assert!(set.insert(Frame::Double(f64::NAN)));
+        assert!(!set.insert(Frame::Double(f64::NAN)));
+        assert!(set.insert(Frame::Error(Bytes::from("foo"))));
+        assert!(set.insert(Frame::Integer(1)));
+        assert!(set.insert(Frame::Double(1.0)));
+        assert_eq!(set.len(), 5)
+    }
+
+    #[test]
+    fn hash_set_given_maps_in_different_order_keeps_both() {
+        let mut set = HashSet::new();
+        set.insert(Frame::Map(vec![
+            Frame::Integer(1),
+            Frame::Null,
+            Frame::Integer(2),
+            Frame::Null,
+        ]));
+        set.insert(Frame::Map(vec![
+            Frame::Integer(2),
+            Frame::Null,
+            Frame::Integer(1),
+            Frame::Null,
+        ]));
+        assert_eq!(set.len(), 2)
+    }
+
+    #[test]
+    fn eq_given_signed_zero_doubles_returns_true() {
+        assert_eq!(Frame::Double(0.0), Frame::Double(-0.0));
+        let mut set = HashSet::new();
+        set.insert(Frame::Double(0.0));
+        assert!(!set.insert(Frame::Double(-0.0)))
+    }
+
+    /// Records every visited value as a line of text
+    #[derive(Default)]
+    struct Recorder(Vec<String>);
+
+    impl FrameVisitor for Recorder {
+        fn visit_string(&mut self, data: &[u8]) -> Result<(), CommandError> {
+            self.0
+                .push(format!("string {}", String::from_utf8_lossy(data)));
+            Ok(())
+        }
+
+        fn visit_integer(&mut self, val: i64) -> Result<(), CommandError> {
+            self.0.push(format!("integer {}", val));
+            Ok(())
+        }
+
+        fn visit_null(&mut self) -> Result<(), CommandError> {
+            self.0.push("null".to_string());
+            Ok(())
+        }
+
+        fn start_array(&mut self, len: usize) -> Result<(), CommandError> {
+            self.0.push(format!("array {}", len));
+            Ok(())
+        }
+
+        fn end_array(&mut self) -> Result<(), CommandError> {
+            self.0.push("end array".to_string());
+            Ok(())
+        }
+
+        fn start_map(&mut self, len: usize) -> Result<(), CommandError> {
+            self.0.push(format!("map {}", len));
+            Ok(())
+        }
+
+        fn end_map(&mut self) -> Result<(), CommandError> {
+            self.0.push("end map".to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn visit_given_nested_frame_visits_values_in_order() {
+        let data =
+            b"*4\r\n$?\r\n;2\r\nfo\r\n;1\r\no\r\n;0\r\n#1\r\n%1\r\n-\r\n|1\r\n%2\r\n%3\r\n%4\r\n";
+        let mut cursor = get_cursor_from_bytes(data);
+        check_with_limits(&mut cursor, &FrameLimits::default()).unwrap();
+        assert_eq!(cursor.position() as usize, data.len());
+
+        let mut recorder = Recorder::default();
+        let mut cursor = get_cursor_from_bytes(data);
+        visit(&mut cursor, &mut recorder).unwrap();
+        assert_eq!(cursor.position() as usize, data.len());
+        assert_eq!(
+            recorder.0,
+            vec![
+                "array 4",
+                "string foo",
+                "map 1",
+                "integer 1",
+                "null",
+                "end map",
+                "integer 4",
+                "end array"
+            ]
+        )
+    }
+
+    #[test]
+    fn visit_given_unexpected_value_fails_and_advances_past_frame() {
+        let data = b"*3\r\n%1\r\n^1\r\n%2\r\n%3\r\n";
+        let mut recorder = Recorder::default();
+        let mut cursor = get_cursor_from_bytes(data);
+        assert!(matches!(
+            visit(&mut cursor, &mut recorder),
+            Err(CommandError::IncompatibleType("frame::Boolean", _))
+        ));
+        assert_eq!(recorder.0, vec!["array 3", "integer 1"]);
+        assert_eq!(parse(&mut cursor).unwrap(), Frame::Integer(3))
+    }
+
+    #[test]
+    fn check_with_limits_given_incomplete_frame_returns_incomplete_error() {
+        let mut cursor = get_cursor_from_bytes(b"*2\r\n%1\r\n");
+        assert_eq!(
+            check_with_limits(&mut cursor, &FrameLimits::default()),
+            Err(ParseFrameError::Incomplete)
+        )
+    }
 }
@@ -17,3 +17,45 @@ pub mod client;
 #[warn(missing_docs)]
 /// Contains functions constructing and parsing commands
 pub mod command;
+
+#[warn(missing_debug_implementations)]
+#[warn(missing_docs)]
+/// Contains the conversions allowed between frame kinds when decoding
+pub mod coercion;
+
+#[warn(missing_debug_implementations)]
+#[warn(missing_docs)]
+/// Contains helpers for decoding maps with string keys into structs
+pub mod fields;
+
+#[warn(missing_debug_implementations)]
+#[warn(missing_docs)]
+/// Contains the resolvers used to look up connection hosts
+pub mod resolver;
+
+#[warn(missing_debug_implementations)]
+#[warn(missing_docs)]
+/// Contains the reader for the server's monitor feed
+pub mod monitor;
+
+#[warn(missing_debug_implementations)]
+#[warn(missing_docs)]
+/// Contains the dispatcher routing subscribed messages per channel
+pub mod pubsub;
+
+#[cfg(feature = "socks")]
+#[warn(missing_debug_implementations)]
+#[warn(missing_docs)]
+/// Contains the SOCKS5 proxy support
+pub mod socks;
+
+#[warn(missing_debug_implementations)]
+#[warn(missing_docs)]
+/// Contains the async runtimes connections can be driven by
+pub mod runtime;
+
+#[cfg(test)]
+mod strategies;
+
+#[cfg(test)]
+mod test_server;
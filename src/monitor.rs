@@ -0,0 +1,89 @@
+use crate::command::{Command, CommandError};
+use crate::connection::{Connection, ConnectionError};
+use crate::frame::Frame;
+
+/// Reads the feed of commands the server sends after a MONITOR command.
+/// The connection is dedicated to the feed, it can not be used for other commands while monitoring
+#[derive(Debug)]
+pub struct Monitor {
+    connection: Connection,
+}
+
+impl Monitor {
+    /// Issues the MONITOR command and waits for the server to acknowledge it with OK
+    pub async fn start(mut connection: Connection) -> Result<Self, CommandError> {
+        let reply: String = Command::from_args(&["MONITOR"])
+            .query(&mut connection)
+            .await?;
+        if reply != "OK" {
+            return Err(CommandError::Decode);
+        }
+        Ok(Monitor { connection })
+    }
+
+    /// Waits for the next frame in the feed. `ConnectionError::Eof` is returned once the server closes the feed
+    pub async fn next(&mut self) -> Result<Frame, ConnectionError> {
+        self.connection.read_frame().await
+    }
+
+    /// Returns the underlying connection, any frames still buffered belong to the feed
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionOptions;
+    use bytes::Bytes;
+    use tokio::net::TcpListener;
+
+    async fn monitor_server(replies: Vec<Frame>) -> ConnectionOptions {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = ConnectionOptions::new("127.0.0.1", listener.local_addr().unwrap().port());
+        let server_options = options.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = Connection::from_stream(stream, &server_options);
+            connection.read_frame().await.unwrap();
+            for reply in &replies {
+                connection.write_frame(reply).await.unwrap();
+            }
+        });
+        options
+    }
+
+    #[tokio::test]
+    async fn next_given_ok_followed_by_feed_yields_each_frame() {
+        let options = monitor_server(vec![
+            Frame::String(Bytes::from("OK")),
+            Frame::String(Bytes::from("\"GET\" \"foo\"")),
+            Frame::String(Bytes::from("\"SET\" \"foo\" \"bar\"")),
+        ])
+        .await;
+        let connection = Connection::connect(&options).await.unwrap();
+        let mut monitor = Monitor::start(connection).await.unwrap();
+
+        assert_eq!(
+            monitor.next().await.unwrap(),
+            Frame::String(Bytes::from("\"GET\" \"foo\""))
+        );
+        assert_eq!(
+            monitor.next().await.unwrap(),
+            Frame::String(Bytes::from("\"SET\" \"foo\" \"bar\""))
+        );
+        assert!(matches!(monitor.next().await, Err(ConnectionError::Eof)))
+    }
+
+    #[tokio::test]
+    async fn start_given_error_reply_returns_query_error() {
+        let options = monitor_server(vec![Frame::Error(Bytes::from("ERR unknown command"))]).await;
+        let connection = Connection::connect(&options).await.unwrap();
+
+        assert!(matches!(
+            Monitor::start(connection).await,
+            Err(CommandError::QueryError(message)) if message == "ERR unknown command"
+        ))
+    }
+}
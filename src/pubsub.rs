@@ -0,0 +1,200 @@
+use crate::command::{Command, CommandError};
+use crate::connection::Connection;
+use crate::frame::Frame;
+use bytes::Bytes;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+/// Routes the messages of a subscription connection into a bounded channel per subscribed channel.
+/// Messages are expected as `["message", channel, payload]` arrays, and the subscribe and unsubscribe
+/// acknowledgements the server interleaves with them are skipped. The connection is dedicated to the subscription,
+/// it can not be used for other commands while dispatching
+#[derive(Debug)]
+pub struct Dispatcher {
+    connection: Connection,
+    channels: HashMap<Bytes, mpsc::Sender<Frame>>,
+}
+
+impl Dispatcher {
+    /// Creates a dispatcher which is not subscribed to any channel
+    pub fn new(connection: Connection) -> Self {
+        Dispatcher {
+            connection,
+            channels: HashMap::new(),
+        }
+    }
+
+    /// Subscribes to the channel and returns the receiver its message payloads are forwarded to.
+    /// At most `capacity` messages are buffered, once the receiver is full dispatching waits for it,
+    /// which stops reading from the connection and pushes back on the server
+    pub async fn subscribe(
+        &mut self,
+        channel: &str,
+        capacity: usize,
+    ) -> Result<mpsc::Receiver<Frame>, CommandError> {
+        let (sender, receiver) = mpsc::channel(capacity);
+        self.send(&["SUBSCRIBE", channel]).await?;
+        self.channels
+            .insert(Bytes::copy_from_slice(channel.as_bytes()), sender);
+        Ok(receiver)
+    }
+
+    /// Unsubscribes from the channel and closes its receiver, messages already forwarded can still be received
+    pub async fn unsubscribe(&mut self, channel: &str) -> Result<(), CommandError> {
+        self.channels.remove(channel.as_bytes());
+        self.send(&["UNSUBSCRIBE", channel]).await
+    }
+
+    /// Returns the number of channels messages are dispatched to
+    pub fn channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Reads the next frame and forwards it if it is a message for a subscribed channel.
+    /// A channel whose receiver has been dropped is unsubscribed from when its next message arrives
+    pub async fn dispatch_next(&mut self) -> Result<(), CommandError> {
+        let (channel, payload) = match self.connection.read_frame().await? {
+            Frame::Error(val) => {
+                return Err(CommandError::QueryError(
+                    String::from_utf8_lossy(&val).into_owned(),
+                ))
+            }
+            Frame::Array(mut parts)
+                if parts.len() == 3 && parts[0] == Frame::String(Bytes::from("message")) =>
+            {
+                let payload = parts.pop().unwrap_or(Frame::Null);
+                match parts.pop() {
+                    Some(Frame::String(channel)) => (channel, payload),
+                    _ => return Err(CommandError::Decode),
+                }
+            }
+            _ => return Ok(()),
+        };
+
+        let sender = match self.channels.get(&channel) {
+            Some(sender) => sender,
+            None => return Ok(()),
+        };
+        if sender.send(payload).await.is_err() {
+            self.channels.remove(&channel);
+            let channel = String::from_utf8_lossy(&channel).into_owned();
+            self.send(&["UNSUBSCRIBE", &channel]).await?;
+        }
+        Ok(())
+    }
+
+    /// Dispatches messages until no channel is subscribed to or an error occurs
+    pub async fn run(&mut self) -> Result<(), CommandError> {
+        while !self.channels.is_empty() {
+            self.dispatch_next().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the underlying connection, any frames still buffered belong to the subscription
+    pub fn into_inner(self) -> Connection {
+        self.connection
+    }
+
+    async fn send(&mut self, args: &[&str]) -> Result<(), CommandError> {
+        let cmd = Command::from_args(args).into_frame();
+        self.connection.write_frame(&cmd).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::ConnectionOptions;
+    use tokio::net::TcpListener;
+
+    fn message(channel: &str, payload: &str) -> Frame {
+        Frame::Array(vec![
+            Frame::String(Bytes::from("message")),
+            Frame::String(Bytes::copy_from_slice(channel.as_bytes())),
+            Frame::String(Bytes::copy_from_slice(payload.as_bytes())),
+        ])
+    }
+
+    fn subscribed(channel: &str, count: i64) -> Frame {
+        Frame::Array(vec![
+            Frame::String(Bytes::from("subscribe")),
+            Frame::String(Bytes::copy_from_slice(channel.as_bytes())),
+            Frame::Integer(count),
+        ])
+    }
+
+    /// Starts a server which waits for the given number of commands and then writes the frames
+    async fn pubsub_server(commands: usize, frames: Vec<Frame>) -> ConnectionOptions {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let options = ConnectionOptions::new("127.0.0.1", listener.local_addr().unwrap().port());
+        let server_options = options.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut connection = Connection::from_stream(stream, &server_options);
+            for _ in 0..commands {
+                connection.read_frame().await.unwrap();
+            }
+            for frame in &frames {
+                connection.write_frame(frame).await.unwrap();
+            }
+            while connection.read_frame().await.is_ok() {}
+        });
+        options
+    }
+
+    #[tokio::test]
+    async fn dispatch_next_given_two_channels_routes_messages_to_their_receivers() {
+        let options = pubsub_server(
+            2,
+            vec![
+                subscribed("foo", 1),
+                subscribed("bar", 2),
+                message("foo", "1"),
+                message("bar", "2"),
+                message("baz", "3"),
+                message("foo", "4"),
+            ],
+        )
+        .await;
+        let connection = Connection::connect(&options).await.unwrap();
+        let mut dispatcher = Dispatcher::new(connection);
+        let mut foo = dispatcher.subscribe("foo", 8).await.unwrap();
+        let mut bar = dispatcher.subscribe("bar", 8).await.unwrap();
+
+        for _ in 0..6 {
+            dispatcher.dispatch_next().await.unwrap();
+        }
+        assert_eq!(foo.recv().await, Some(Frame::String(Bytes::from("1"))));
+        assert_eq!(foo.recv().await, Some(Frame::String(Bytes::from("4"))));
+        assert_eq!(bar.recv().await, Some(Frame::String(Bytes::from("2"))));
+        assert!(foo.try_recv().is_err());
+        assert!(bar.try_recv().is_err())
+    }
+
+    #[tokio::test]
+    async fn unsubscribe_closes_receiver_after_forwarded_messages() {
+        let options = pubsub_server(1, vec![message("foo", "1")]).await;
+        let connection = Connection::connect(&options).await.unwrap();
+        let mut dispatcher = Dispatcher::new(connection);
+        let mut foo = dispatcher.subscribe("foo", 1).await.unwrap();
+
+        dispatcher.dispatch_next().await.unwrap();
+        dispatcher.unsubscribe("foo").await.unwrap();
+        assert_eq!(dispatcher.channels(), 0);
+        assert_eq!(foo.recv().await, Some(Frame::String(Bytes::from("1"))));
+        assert_eq!(foo.recv().await, None)
+    }
+
+    #[tokio::test]
+    async fn run_given_dropped_receiver_unsubscribes_and_returns() {
+        let options = pubsub_server(1, vec![message("foo", "1")]).await;
+        let connection = Connection::connect(&options).await.unwrap();
+        let mut dispatcher = Dispatcher::new(connection);
+        drop(dispatcher.subscribe("foo", 1).await.unwrap());
+
+        dispatcher.run().await.unwrap();
+        assert_eq!(dispatcher.channels(), 0)
+    }
+}
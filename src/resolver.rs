@@ -0,0 +1,25 @@
+use std::fmt::Debug;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use tokio::net::lookup_host;
+
+/// Future returned by a resolver
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send + 'a>>;
+
+/// Used to resolve a host and port to the candidate addresses a connection is attempted on
+pub trait Resolver: Debug + Send + Sync {
+    /// Resolves the host and port to a list of socket addresses
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> ResolveFuture<'a>;
+}
+
+/// Resolves hosts using the system resolver through tokio
+#[derive(Debug, Default)]
+pub struct DefaultResolver;
+
+impl Resolver for DefaultResolver {
+    fn resolve<'a>(&'a self, host: &'a str, port: u16) -> ResolveFuture<'a> {
+        Box::pin(async move { Ok(lookup_host((host, port)).await?.collect()) })
+    }
+}
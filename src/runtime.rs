@@ -0,0 +1,343 @@
+use crate::resolver::{DefaultResolver, Resolver};
+use socket2::SockRef;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::{TcpSocket, TcpStream};
+
+/// Represents the async runtime which drives the connection's socket and timers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Runtime {
+    /// Uses tokio's networking and timers, this requires running inside a tokio runtime
+    #[default]
+    Tokio,
+    /// Uses async-std's networking and timers
+    #[cfg(feature = "async-std")]
+    AsyncStd,
+}
+
+/// Represents the socket of a connection on any of the supported runtimes.
+/// It implements tokio's `AsyncRead` and `AsyncWrite` whichever runtime it belongs to
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Stream {
+    /// A tokio TCP stream
+    Tokio(TcpStream),
+    /// An async-std TCP stream
+    #[cfg(feature = "async-std")]
+    AsyncStd(async_std::net::TcpStream),
+}
+
+impl Runtime {
+    /// Resolves the host with the runtime's system resolver
+    pub(crate) async fn lookup_host(self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        match self {
+            Runtime::Tokio => DefaultResolver.resolve(host, port).await,
+            #[cfg(feature = "async-std")]
+            Runtime::AsyncStd => {
+                use async_std::net::ToSocketAddrs;
+                Ok((host, port).to_socket_addrs().await?.collect())
+            }
+        }
+    }
+
+    /// Connects to the first address which accepts the connection, binding the socket to `bind_addr` first if given
+    pub(crate) async fn connect(
+        self,
+        addrs: &[SocketAddr],
+        bind_addr: Option<SocketAddr>,
+    ) -> io::Result<Stream> {
+        match (self, bind_addr) {
+            (Runtime::Tokio, Some(bind_addr)) => {
+                Ok(Stream::Tokio(connect_from(bind_addr, addrs).await?))
+            }
+            (Runtime::Tokio, None) => Ok(Stream::Tokio(TcpStream::connect(addrs).await?)),
+            #[cfg(feature = "async-std")]
+            (Runtime::AsyncStd, Some(_)) => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "binding to a local address is not supported on async-std",
+            )),
+            #[cfg(feature = "async-std")]
+            (Runtime::AsyncStd, None) => Ok(Stream::AsyncStd(
+                async_std::net::TcpStream::connect(addrs).await?,
+            )),
+        }
+    }
+
+    /// Runs the future to completion, failing with `None` if it does not complete within the duration
+    pub(crate) async fn timeout<F: Future>(
+        self,
+        duration: Duration,
+        future: F,
+    ) -> Option<F::Output> {
+        match self {
+            Runtime::Tokio => tokio::time::timeout(duration, future).await.ok(),
+            #[cfg(feature = "async-std")]
+            Runtime::AsyncStd => async_std::future::timeout(duration, future).await.ok(),
+        }
+    }
+}
+
+impl Stream {
+    /// Returns the address of the remote end of the stream
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Tokio(stream) => stream.peer_addr(),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(stream) => stream.peer_addr(),
+        }
+    }
+
+    /// Returns the local address the stream is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Stream::Tokio(stream) => stream.local_addr(),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(stream) => stream.local_addr(),
+        }
+    }
+
+    /// Returns a reference to the underlying socket for getting and setting socket options
+    pub(crate) fn socket(&self) -> SockRef<'_> {
+        match self {
+            Stream::Tokio(stream) => SockRef::from(stream),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(stream) => SockRef::from(stream),
+        }
+    }
+
+    /// Returns whether the stream supports non-blocking reads and waiting for readiness, which only tokio streams do
+    pub(crate) fn supports_readiness(&self) -> bool {
+        match self {
+            Stream::Tokio(_) => true,
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(_) => false,
+        }
+    }
+
+    /// Reads whatever is available without waiting, this is only supported on tokio
+    pub(crate) fn try_read_buf(&self, buf: &mut bytes::BytesMut) -> io::Result<usize> {
+        match self {
+            Stream::Tokio(stream) => stream.try_read_buf(buf),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// Waits until the stream is readable, this is only supported on tokio
+    pub(crate) async fn readable(&self) -> io::Result<()> {
+        match self {
+            Stream::Tokio(stream) => stream.readable().await,
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+
+    /// Waits until the stream is writable, this is only supported on tokio
+    pub(crate) async fn writable(&self) -> io::Result<()> {
+        match self {
+            Stream::Tokio(stream) => stream.writable().await,
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(_) => Err(io::ErrorKind::Unsupported.into()),
+        }
+    }
+}
+
+impl From<TcpStream> for Stream {
+    fn from(stream: TcpStream) -> Self {
+        Stream::Tokio(stream)
+    }
+}
+
+#[cfg(feature = "async-std")]
+impl From<async_std::net::TcpStream> for Stream {
+    fn from(stream: async_std::net::TcpStream) -> Self {
+        Stream::AsyncStd(stream)
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tokio(stream) => Pin::new(stream).poll_read(cx, buf),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(stream) => {
+                let n = match async_std::io::Read::poll_read(
+                    Pin::new(stream),
+                    cx,
+                    buf.initialize_unfilled(),
+                ) {
+                    Poll::Ready(Ok(n)) => n,
+                    Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                    Poll::Pending => return Poll::Pending,
+                };
+                buf.advance(n);
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        data: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        match self.get_mut() {
+            Stream::Tokio(stream) => Pin::new(stream).poll_write(cx, data),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(stream) => {
+                async_std::io::Write::poll_write(Pin::new(stream), cx, data)
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tokio(stream) => Pin::new(stream).poll_flush(cx),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(stream) => async_std::io::Write::poll_flush(Pin::new(stream), cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self.get_mut() {
+            Stream::Tokio(stream) => Pin::new(stream).poll_shutdown(cx),
+            #[cfg(feature = "async-std")]
+            Stream::AsyncStd(stream) => async_std::io::Write::poll_close(Pin::new(stream), cx),
+        }
+    }
+}
+
+async fn connect_from(bind_addr: SocketAddr, addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut last_err = None;
+    // A socket can only be bound to an address of its own family, so addresses of the other family are skipped
+    for addr in addrs
+        .iter()
+        .filter(|addr| addr.is_ipv4() == bind_addr.is_ipv4())
+    {
+        let socket = if addr.is_ipv4() {
+            TcpSocket::new_v4()?
+        } else {
+            TcpSocket::new_v6()?
+        };
+        socket.bind(bind_addr)?;
+        match socket.connect(*addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_err = Some(e),
+        }
+    }
+
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "could not resolve to any address of the bind address's family",
+        )
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    #[cfg(feature = "async-std")]
+    use crate::connection::{ConnectionError, ConnectionOptions};
+    use crate::frame::Frame;
+    use crate::test_server::TestServer;
+
+    #[tokio::test]
+    async fn connect_given_tokio_runtime_round_trips_frames() {
+        let server = TestServer::start(|frame| frame).await;
+        let mut options = server.options();
+        options
+            .set_runtime(Runtime::Tokio)
+            .set_read_timeout(Duration::from_secs(5));
+        let mut connection = Connection::connect(&options).await.unwrap();
+
+        connection.write_frame(&Frame::Integer(1)).await.unwrap();
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1))
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn connect_given_async_std_runtime_round_trips_frames() {
+        async_std::task::block_on(async {
+            let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let mut options =
+                ConnectionOptions::new("127.0.0.1", listener.local_addr().unwrap().port());
+            options
+                .set_runtime(Runtime::AsyncStd)
+                .set_read_timeout(Duration::from_secs(5));
+            let server_options = options.clone();
+            let server = async_std::task::spawn(async move {
+                let (stream, _) = listener.accept().await.unwrap();
+                let mut connection = Connection::from_stream(stream, &server_options);
+                let frame = connection.read_frame().await.unwrap();
+                connection.write_frame(&frame).await.unwrap();
+            });
+
+            let mut connection = Connection::connect(&options).await.unwrap();
+            connection.write_frame(&Frame::Integer(1)).await.unwrap();
+            assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1));
+            server.await
+        })
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn drain_pending_given_async_std_runtime_returns_unsupported() {
+        async_std::task::block_on(async {
+            let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let mut options =
+                ConnectionOptions::new("127.0.0.1", listener.local_addr().unwrap().port());
+            options.set_runtime(Runtime::AsyncStd);
+
+            let mut connection = Connection::connect(&options).await.unwrap();
+            assert!(matches!(
+                connection.drain_pending(),
+                Err(ConnectionError::Unsupported("drain_pending"))
+            ));
+            assert!(matches!(
+                connection.readable().await,
+                Err(ConnectionError::Unsupported("readable"))
+            ));
+            drop(listener)
+        })
+    }
+
+    #[cfg(feature = "async-std")]
+    #[test]
+    fn read_frame_given_async_std_runtime_times_out_without_tokio() {
+        async_std::task::block_on(async {
+            let listener = async_std::net::TcpListener::bind("127.0.0.1:0")
+                .await
+                .unwrap();
+            let mut options =
+                ConnectionOptions::new("127.0.0.1", listener.local_addr().unwrap().port());
+            options
+                .set_runtime(Runtime::AsyncStd)
+                .set_read_timeout(Duration::from_millis(10));
+
+            let mut connection = Connection::connect(&options).await.unwrap();
+            assert!(matches!(
+                connection.read_frame().await,
+                Err(ConnectionError::Timeout)
+            ));
+            drop(listener)
+        })
+    }
+}
@@ -0,0 +1,303 @@
+use crate::connection::{self, ConnectionError, ConnectionOptions};
+use crate::runtime::Stream;
+use std::fmt;
+use std::net::IpAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+/// Represents a SOCKS5 proxy connections are routed through. The proxy resolves the Segment host,
+/// so the host does not have to be resolvable from the client
+#[derive(Clone)]
+pub struct Socks5Proxy {
+    host: String,
+    port: u16,
+    credentials: Option<(String, String)>,
+}
+
+/// Shows the username but never the password, which would otherwise end up in logs of the options or client
+impl fmt::Debug for Socks5Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Socks5Proxy")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field(
+                "credentials",
+                &self
+                    .credentials
+                    .as_ref()
+                    .map(|(username, _)| (username, "<redacted>")),
+            )
+            .finish()
+    }
+}
+
+impl Socks5Proxy {
+    /// Creates a proxy which is connected to without authentication
+    pub fn new(host: &str, port: u16) -> Self {
+        Socks5Proxy {
+            host: host.to_string(),
+            port,
+            credentials: None,
+        }
+    }
+
+    /// Sets the username and password used to authenticate with the proxy
+    pub fn set_credentials(&mut self, username: &str, password: &str) -> &mut Self {
+        self.credentials = Some((username.to_string(), password.to_string()));
+        self
+    }
+
+    /// Returns the proxy host
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Returns the proxy port
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Returns the username used to authenticate with the proxy
+    pub fn username(&self) -> Option<&str> {
+        self.credentials
+            .as_ref()
+            .map(|(username, _)| username.as_str())
+    }
+
+    /// Connects to the proxy and asks it to connect to the host and port of the options.
+    /// The proxy host is resolved and connected to like the Segment host would be without a proxy
+    pub(crate) async fn connect(
+        &self,
+        options: &ConnectionOptions,
+    ) -> Result<Stream, ConnectionError> {
+        let mut stream = connection::connect_stream(&self.host, self.port, options).await?;
+        self.handshake(&mut stream, options.host(), options.port())
+            .await?;
+        Ok(stream)
+    }
+
+    async fn handshake<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        host: &str,
+        port: u16,
+    ) -> Result<(), ConnectionError> {
+        let method = match self.credentials {
+            Some(_) => USERNAME_PASSWORD,
+            None => NO_AUTH,
+        };
+        stream.write_all(&[VERSION, 1, method]).await?;
+        let mut reply = [0; 2];
+        stream.read_exact(&mut reply).await?;
+        if reply[0] != VERSION {
+            return Err(proxy_error("proxy does not speak SOCKS5"));
+        }
+        if reply[1] == NO_ACCEPTABLE_METHOD {
+            return Err(proxy_error("proxy rejected the authentication method"));
+        }
+        if reply[1] != method {
+            return Err(proxy_error(
+                "proxy chose an authentication method which was not offered",
+            ));
+        }
+
+        if let Some((username, password)) = &self.credentials {
+            let mut request = vec![1];
+            push_with_len(&mut request, username.as_bytes(), "username")?;
+            push_with_len(&mut request, password.as_bytes(), "password")?;
+            stream.write_all(&request).await?;
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0 {
+                return Err(proxy_error("proxy rejected the credentials"));
+            }
+        }
+
+        let mut request = vec![VERSION, CONNECT, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(addr)) => {
+                request.push(IPV4);
+                request.extend_from_slice(&addr.octets());
+            }
+            Ok(IpAddr::V6(addr)) => {
+                request.push(IPV6);
+                request.extend_from_slice(&addr.octets());
+            }
+            Err(_) => {
+                request.push(DOMAIN_NAME);
+                push_with_len(&mut request, host.as_bytes(), "host")?;
+            }
+        }
+        request.extend_from_slice(&port.to_be_bytes());
+        stream.write_all(&request).await?;
+
+        let mut reply = [0; 4];
+        stream.read_exact(&mut reply).await?;
+        if reply[1] != 0 {
+            return Err(ConnectionError::ProxyError(format!(
+                "proxy failed to connect with reply code {}",
+                reply[1]
+            )));
+        }
+        let bound_addr_len = match reply[3] {
+            IPV4 => 4,
+            IPV6 => 16,
+            DOMAIN_NAME => stream.read_u8().await? as usize,
+            _ => return Err(proxy_error("proxy replied with an unknown address type")),
+        };
+        let mut bound_addr = vec![0; bound_addr_len + 2];
+        stream.read_exact(&mut bound_addr).await?;
+        Ok(())
+    }
+}
+
+fn push_with_len(request: &mut Vec<u8>, data: &[u8], context: &str) -> Result<(), ConnectionError> {
+    let len = u8::try_from(data.len()).map_err(|_| {
+        ConnectionError::ProxyError(format!("{} is longer than 255 bytes", context))
+    })?;
+    request.push(len);
+    request.extend_from_slice(data);
+    Ok(())
+}
+
+fn proxy_error(message: &str) -> ConnectionError {
+    ConnectionError::ProxyError(message.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::connection::Connection;
+    use crate::frame::Frame;
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// Starts a SOCKS5 proxy which expects the credentials if given and a connect request to `segment.internal:1698`,
+    /// then echoes the frames it receives instead of forwarding them
+    async fn mock_proxy(credentials: Option<(&'static str, &'static str)>) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut greeting = [0; 3];
+            stream.read_exact(&mut greeting).await.unwrap();
+            let method = match credentials {
+                Some(_) => USERNAME_PASSWORD,
+                None => NO_AUTH,
+            };
+            if greeting != [VERSION, 1, method] {
+                stream
+                    .write_all(&[VERSION, NO_ACCEPTABLE_METHOD])
+                    .await
+                    .unwrap();
+                return;
+            }
+            stream.write_all(&[VERSION, method]).await.unwrap();
+
+            if let Some((username, password)) = credentials {
+                let mut expected = vec![1, username.len() as u8];
+                expected.extend_from_slice(username.as_bytes());
+                expected.push(password.len() as u8);
+                expected.extend_from_slice(password.as_bytes());
+                let mut request = vec![0; expected.len()];
+                stream.read_exact(&mut request).await.unwrap();
+                assert_eq!(request, expected);
+                stream.write_all(&[1, 0]).await.unwrap();
+            }
+
+            let mut request = vec![0; 5 + "segment.internal".len() + 2];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(&request[..5], &[VERSION, CONNECT, 0, DOMAIN_NAME, 16]);
+            assert_eq!(&request[5..21], b"segment.internal");
+            assert_eq!(&request[21..], &1698u16.to_be_bytes());
+            stream
+                .write_all(&[VERSION, 0, 0, IPV4, 127, 0, 0, 1, 0, 0])
+                .await
+                .unwrap();
+
+            let mut connection = Connection::from_stream(stream, &ConnectionOptions::default());
+            while let Ok(frame) = connection.read_frame().await {
+                connection.write_frame(&frame).await.unwrap();
+            }
+        });
+        port
+    }
+
+    #[test]
+    fn debug_redacts_password() {
+        let mut proxy = Socks5Proxy::new("127.0.0.1", 1080);
+        proxy.set_credentials("user", "hunter2");
+        let debug = format!("{:?}", proxied_options(proxy));
+        assert!(debug.contains("user"));
+        assert!(!debug.contains("hunter2"))
+    }
+
+    fn proxied_options(proxy: Socks5Proxy) -> ConnectionOptions {
+        let mut options = ConnectionOptions::new("segment.internal", 1698);
+        options.set_socks5_proxy(proxy);
+        options
+    }
+
+    #[tokio::test]
+    async fn connect_given_socks5_proxy_tunnels_frames() {
+        let port = mock_proxy(None).await;
+        let options = proxied_options(Socks5Proxy::new("127.0.0.1", port));
+
+        let mut connection = Connection::connect(&options).await.unwrap();
+        connection.write_frame(&Frame::Integer(1)).await.unwrap();
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Integer(1))
+    }
+
+    #[tokio::test]
+    async fn connect_given_socks5_credentials_authenticates() {
+        let port = mock_proxy(Some(("user", "secret"))).await;
+        let mut proxy = Socks5Proxy::new("127.0.0.1", port);
+        proxy.set_credentials("user", "secret");
+        let options = proxied_options(proxy);
+
+        let mut connection = Connection::connect(&options).await.unwrap();
+        connection.write_frame(&Frame::Null).await.unwrap();
+        assert_eq!(connection.read_frame().await.unwrap(), Frame::Null)
+    }
+
+    #[tokio::test]
+    async fn connect_given_missing_credentials_returns_proxy_error() {
+        let port = mock_proxy(Some(("user", "secret"))).await;
+        let options = proxied_options(Socks5Proxy::new("127.0.0.1", port));
+
+        assert!(matches!(
+            Connection::connect(&options).await,
+            Err(ConnectionError::ProxyError(_))
+        ))
+    }
+
+    #[tokio::test]
+    async fn handshake_given_ipv4_host_sends_address() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (client, accepted) = tokio::join!(TcpStream::connect(addr), listener.accept());
+        let (mut client, (mut server, _)) = (client.unwrap(), accepted.unwrap());
+        server.write_all(&[VERSION, NO_AUTH]).await.unwrap();
+        server
+            .write_all(&[VERSION, 0, 0, IPV4, 127, 0, 0, 1, 0, 0])
+            .await
+            .unwrap();
+
+        Socks5Proxy::new("127.0.0.1", addr.port())
+            .handshake(&mut client, "10.0.0.1", 1698)
+            .await
+            .unwrap();
+        let mut request = [0; 13];
+        server.read_exact(&mut request).await.unwrap();
+        assert_eq!(
+            request,
+            [VERSION, 1, NO_AUTH, VERSION, CONNECT, 0, IPV4, 10, 0, 0, 1, 6, 162]
+        )
+    }
+}
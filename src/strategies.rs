@@ -0,0 +1,58 @@
+use crate::command::{CommandError, FromSegmentFrame, ToSegmentFrame};
+use crate::frame::{self, Frame};
+use bytes::{Bytes, BytesMut};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::io::Cursor;
+
+/// Generates byte strings, including empty ones and ones containing CRLF, which shrink towards shorter strings
+pub(crate) fn bytes() -> impl Strategy<Value = Bytes> {
+    vec(any::<u8>(), 0..64).prop_map(Bytes::from)
+}
+
+/// Generates doubles which survive a round trip, i.e. anything but NaN whose payload is not preserved on the wire
+/// and which is never equal to itself once decoded
+pub(crate) fn double() -> impl Strategy<Value = f64> {
+    any::<f64>().prop_filter("NaN does not round trip", |val| !val.is_nan())
+}
+
+/// Generates scalar frames
+pub(crate) fn scalar_frame() -> impl Strategy<Value = Frame> {
+    prop_oneof![
+        bytes().prop_map(Frame::String),
+        any::<i64>().prop_map(Frame::Integer),
+        any::<bool>().prop_map(Frame::Boolean),
+        Just(Frame::Null),
+        double().prop_map(Frame::Double),
+        bytes().prop_map(Frame::Error),
+    ]
+}
+
+/// Generates frames with arrays and maps nested a few levels deep, which shrink towards scalars
+pub(crate) fn frame() -> impl Strategy<Value = Frame> {
+    scalar_frame().prop_recursive(4, 64, 8, |inner| {
+        prop_oneof![
+            vec(inner.clone(), 0..8).prop_map(Frame::Array),
+            vec((inner.clone(), inner), 0..4).prop_map(|pairs| {
+                Frame::Map(pairs.into_iter().flat_map(|(k, v)| [k, v]).collect())
+            }),
+        ]
+    })
+}
+
+/// Serializes the frame as it is written to a connection and parses it back
+pub(crate) fn reparse(frame: &Frame) -> Frame {
+    let mut buf = BytesMut::new();
+    frame::serialize(frame, &mut buf);
+    let mut cursor = Cursor::new(&buf[..]);
+    let parsed = frame::parse(&mut cursor).unwrap();
+    assert_eq!(cursor.position() as usize, buf.len());
+    parsed
+}
+
+/// Converts the value to a frame, sends it through serialization and parsing and decodes it back
+pub(crate) fn round_trip<T: ToSegmentFrame + FromSegmentFrame>(
+    value: &T,
+) -> Result<T, CommandError> {
+    T::from_segment_frame(&reparse(&value.to_segment_frame()))
+}
@@ -0,0 +1,78 @@
+use crate::connection::{Connection, ConnectionOptions};
+use crate::frame::Frame;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+
+/// A minimal Segment server used in tests. Every frame it receives is passed to the handler
+/// and the returned frame is written back as the reply
+pub(crate) struct TestServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl TestServer {
+    /// Starts the server on a random local port
+    pub(crate) async fn start<F>(handler: F) -> Self
+    where
+        F: Fn(Frame) -> Frame + Send + Sync + 'static,
+    {
+        Self::start_delayed(Duration::ZERO, handler).await
+    }
+
+    /// Starts the server like `start`, but waits for the delay before writing each reply without blocking the runtime
+    pub(crate) async fn start_delayed<F>(delay: Duration, handler: F) -> Self
+    where
+        F: Fn(Frame) -> Frame + Send + Sync + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler = Arc::new(handler);
+        let handle = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let handler = handler.clone();
+                tokio::spawn(async move {
+                    let options = ConnectionOptions::new("127.0.0.1", addr.port());
+                    let mut connection = Connection::from_stream(stream, &options);
+                    while let Ok(frame) = connection.read_frame().await {
+                        let reply = handler(frame);
+                        if !delay.is_zero() {
+                            tokio::time::sleep(delay).await;
+                        }
+                        if connection.write_frame(&reply).await.is_err() {
+                            break;
+                        }
+                    }
+                });
+            }
+        });
+
+        TestServer { addr, handle }
+    }
+
+    /// Starts a server which replies to `ECHO payload` with the payload and to anything else with an error
+    pub(crate) async fn echo() -> Self {
+        Self::start(|frame| match frame {
+            Frame::Array(mut args)
+                if args.len() == 2 && args[0] == Frame::String("ECHO".into()) =>
+            {
+                args.pop().unwrap()
+            }
+            _ => Frame::Error("ERR unknown command".into()),
+        })
+        .await
+    }
+
+    /// Returns the options for connecting to the server
+    pub(crate) fn options(&self) -> ConnectionOptions {
+        ConnectionOptions::new("127.0.0.1", self.addr.port())
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
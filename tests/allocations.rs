@@ -0,0 +1,97 @@
+use bytes::Bytes;
+use segment_rs::command::Command;
+use segment_rs::connection::{Connection, ConnectionOptions};
+use segment_rs::frame::Frame;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{Read, Write};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn allocations() -> usize {
+    ALLOCATIONS.with(|count| count.get())
+}
+
+#[test]
+fn inspecting_error_frame_does_not_allocate() {
+    let frame = Frame::Error(Bytes::from_static(b"WRONGTYPE not a string"));
+
+    let before = allocations();
+    let code = frame.error_code();
+    let message = frame.error_message();
+    let after = allocations();
+
+    assert_eq!(code, Some(&b"WRONGTYPE"[..]));
+    assert_eq!(message, Some(&b"WRONGTYPE not a string"[..]));
+    assert_eq!(after - before, 0)
+}
+
+/// Starts a server on its own thread, so its allocations are not counted, which replies to each command
+/// with the next of the replies
+fn reply_server(replies: &'static [&'static [u8]]) -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut buf = [0; 1024];
+        for reply in replies {
+            if stream.read(&mut buf).unwrap() == 0 {
+                return;
+            }
+            stream.write_all(reply).unwrap();
+        }
+    });
+    port
+}
+
+async fn query_frame_allocations(connection: &mut Connection) -> (usize, Frame) {
+    let before = allocations();
+    let frame = Command::from_args(&["GET", "foo"])
+        .query_frame(connection)
+        .await
+        .unwrap();
+    (allocations() - before, frame)
+}
+
+#[tokio::test]
+async fn query_frame_given_error_reply_allocates_no_more_than_string_reply() {
+    let port = reply_server(&[
+        b"$22\r\nWRONGTYPE not a string\r\n",
+        b"$22\r\nWRONGTYPE not a string\r\n",
+        b"!22\r\nWRONGTYPE not a string\r\n",
+    ]);
+    let mut connection = Connection::connect(&ConnectionOptions::new("127.0.0.1", port))
+        .await
+        .unwrap();
+    query_frame_allocations(&mut connection).await;
+
+    let (string_allocations, _) = query_frame_allocations(&mut connection).await;
+    let (error_allocations, frame) = query_frame_allocations(&mut connection).await;
+    let before = allocations();
+    let code = frame.error_code();
+    let message = frame.error_message();
+    let after = allocations();
+
+    assert_eq!(code, Some(&b"WRONGTYPE"[..]));
+    assert_eq!(message, Some(&b"WRONGTYPE not a string"[..]));
+    assert_eq!(after - before, 0);
+    assert!(error_allocations <= string_allocations)
+}